@@ -0,0 +1,360 @@
+use std::sync::{Arc, LazyLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use bindings::region::*;
+use bindings::ext::ctx::*;
+use bindings::sdk::{DbContext, Table, Timestamp};
+use tokio::sync::mpsc::{self, error::TrySendError, unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
+use tracing::Instrument;
+use crate::glue::{Config, Configurable};
+use crate::message::{Message, Sequence};
+use crate::sieve::{self, SharedNames, Snapshot};
+use crate::sink::{self, Batch, Outlet, Sink};
+use crate::{metrics, outbox};
+
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// follows the config of one region through reloads, regions are fixed until a restart so the index stays valid.
+pub fn region(mut rx_config: watch::Receiver<Config>, index: usize) -> watch::Receiver<Config> {
+    let (tx, rx) = watch::channel(rx_config.borrow().regions().swap_remove(index));
+    tokio::spawn(async move {
+        while rx_config.changed().await.is_ok() {
+            let Some(config) = rx_config.borrow_and_update().regions().into_iter().nth(index) else { continue };
+            if tx.send(config).is_err() { break }
+        }
+    });
+    rx
+}
+
+// signalled by the sdk when the connection drops, remembering whether it was the token that got refused.
+#[derive(Default)]
+struct Disconnected {
+    notify:   Notify,
+    rejected: AtomicBool,
+}
+
+// one region's connection, sieve and sinks, until the stop signal.
+// `names` is kept up to date by the sieve for as long as this runs, whoever holds a clone can resolve the region's ids.
+pub async fn run(rx_config: watch::Receiver<Config>, rx_stop: watch::Receiver<bool>, names: SharedNames) -> Result<()> {
+    let (tx_ctx, rx_ctx) = unbounded_channel::<DbUpdate>();
+    let (tx_update, rx_update) = mpsc::channel::<DbUpdate>(rx_config.borrow().update_capacity());
+    let (tx_snapshot, rx_snapshot) = unbounded_channel::<Snapshot>();
+    let (tx_msg, rx_msg) = {
+        let config = rx_config.borrow();
+        metrics::region(config.region());
+        outbox::bounded(config.message_capacity(), config.overflow())
+    };
+
+    tokio::spawn(relay(rx_ctx, tx_update));
+    let seq = Sequence::default();
+
+    let sieve_task = tokio::spawn(
+        sieve::sieve(rx_update, rx_snapshot, tx_msg.clone(), rx_config.clone(), names, seq.clone()).instrument(tracing::info_span!("sieve"))
+    );
+    let sinks = sink::from_config(&rx_config);
+    let consume_task = tokio::spawn(consume(rx_msg, rx_config.clone(), sinks, seq.clone()).instrument(tracing::info_span!("consume")));
+
+    let mut backoff = BACKOFF_MIN;
+    // only the first connection gives up, once the bridge ran it keeps reconnecting for good.
+    let mut ever_connected = false;
+    let mut failed = 0;
+    let mut result = Ok(());
+    let mut token = None;
+    loop {
+        let connected_at = Instant::now();
+        let disconnected = Arc::new(Disconnected::default());
+
+        // a token set in the config arrives with a reload, one kept in token_file is read again here.
+        let mut config = rx_config.borrow().clone();
+        if let Err(e) = config.refresh_token() { tracing::warn!(error = %e, "failed to refresh token, using the previous one"); }
+        if token.as_ref().is_some_and(|token| token != config.token()) { tracing::info!("picked up a new token"); }
+        token = Some(config.token().clone());
+
+        let connection = connect(&config, tx_ctx.clone(), tx_snapshot.clone(), tx_msg.clone(), seq.clone(), disconnected.clone());
+        match connection {
+            Ok(ctx) => {
+                ever_connected = true;
+                let stop = rx_stop.clone();
+                let signal = disconnected.clone();
+                let until = async move {
+                    tokio::select! {
+                        _ = stopped(stop) => {},
+                        _ = signal.notify.notified() => {},
+                    }
+                };
+                if let Err(e) = ctx.run_until(until).await { tracing::error!(error = ?e, "db error"); }
+                if disconnected.rejected.load(Ordering::Relaxed) && rx_config.borrow().fatal_auth_errors() {
+                    result = Err(anyhow!("the server refused the token, giving up (check token or token_file)"));
+                    break
+                }
+                let region = {
+                    let config = rx_config.borrow();
+                    config.announce_connection().then(|| config.region().to_string())
+                };
+                if *rx_stop.borrow() && let Some(region) = region {
+                    let _ = tx_msg.send(announcement(&seq, &region, "disconnected from")).await;
+                }
+            }
+            Err(e) if is_auth_error(&format!("{:#}", e)) && rx_config.borrow().fatal_auth_errors() => {
+                result = Err(anyhow!("the server refused the token, giving up (check token or token_file): {:#}", e));
+                break
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "failed to connect");
+                failed += 1;
+                let attempts = rx_config.borrow().connect_attempts();
+                if !ever_connected && attempts > 0 && failed >= attempts {
+                    result = Err(anyhow!("could not connect after {} attempts, giving up (check cluster_url, region and token)", failed));
+                    break
+                }
+            }
+        }
+
+        if *rx_stop.borrow() { break }
+        if connected_at.elapsed() > BACKOFF_MAX { backoff = BACKOFF_MIN; }
+
+        tracing::info!(backoff_secs = backoff.as_secs(), "reconnecting...");
+        metrics::reconnecting();
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {},
+            _ = stopped(rx_stop.clone()) => break,
+        }
+        backoff = (backoff * 2).min(BACKOFF_MAX);
+    }
+
+    // the sieve finishes what it already received first, so nothing is sent after the disconnect.
+    drop(tx_ctx);
+    drop(tx_snapshot);
+    let _ = sieve_task.await;
+    let _ = tx_msg.send(Message::Disconnect).await;
+    let _ = consume_task.await;
+    result
+}
+
+#[tracing::instrument(skip_all)]
+fn connect(
+    config: &Config,
+    tx: UnboundedSender<DbUpdate>,
+    tx_snapshot: UnboundedSender<Snapshot>,
+    tx_msg: outbox::Sender,
+    seq: Sequence,
+    disconnected: Arc<Disconnected>,
+) -> Result<DbConnection> {
+    let (region, announce) = (config.region().to_string(), config.announce_connection());
+    let (region_lost, region_applied, tx_lost, seq_lost) = (region.clone(), region.clone(), tx_msg.clone(), seq.clone());
+    let ctx = DbConnection::builder()
+        .configure(config)
+        .on_connect(move |_, _, _| {
+            tracing::info!("connected!");
+            metrics::connected(&region, true);
+            if announce { let _ = tx_msg.try_send(announcement(&seq, &region, "connected to")); }
+        })
+        .on_disconnect(move |_, error| {
+            match error.map(|e| e.to_string()) {
+                Some(e) if is_auth_error(&e) => {
+                    tracing::error!(error = %e, "disconnected, the token was refused");
+                    disconnected.rejected.store(true, Ordering::Relaxed);
+                }
+                Some(e) => tracing::warn!(error = %e, "disconnected!"),
+                None => tracing::warn!("disconnected!"),
+            }
+            metrics::connected(&region_lost, false);
+            metrics::subscribed(&region_lost, false);
+            if announce { let _ = tx_lost.try_send(announcement(&seq_lost, &region_lost, "lost connection to")); }
+            disconnected.notify.notify_one();
+        })
+        .with_channel(tx)
+        .build()?;
+
+    let backfill = config.backfill().as_micros() as i64;
+    let start = Timestamp::from_micros_since_unix_epoch(Timestamp::now().to_micros_since_unix_epoch() - backfill);
+    ctx.subscription_builder()
+        .on_applied(move |ctx| {
+            metrics::subscribed(&region_applied, true);
+            let snapshot = Snapshot {
+                claims: ctx.db().claim_state().iter().map(|c| (c.entity_id, c.name)).collect(),
+                empires: ctx.db().empire_state().iter().map(|e| (e.entity_id, e.name)).collect(),
+                players: ctx.db().player_username_state().iter().map(|p| (p.entity_id, p.username)).collect(),
+            };
+            let _ = tx_snapshot.send(snapshot);
+        })
+        .on_error(|_, err| tracing::error!(error = %err, "subscription error"))
+        .subscribe(subscriptions(config, start));
+
+    Ok(ctx)
+}
+
+// the sdk only takes an unbounded sender and must never wait, this drains it as fast as it fills and drops
+// updates once the sieve is update_capacity behind, rather than letting them pile up while a sink blocks it.
+async fn relay(mut rx: UnboundedReceiver<DbUpdate>, tx: mpsc::Sender<DbUpdate>) {
+    while let Some(update) = rx.recv().await {
+        match tx.try_send(update) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                metrics::dropped("update_overflow");
+                tracing::warn!("sieve is behind, dropped a database update");
+            }
+            Err(TrySendError::Closed(_)) => return,
+        }
+    }
+}
+
+// whole words only, a port, an id or a duration with 401 in it says nothing about the token.
+static AUTH_ERROR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(40[13]|unauthorized|forbidden|invalid token|token expired)\b").unwrap()
+});
+
+// the sdk reports a refused token only through its message, the handshake failing with 401 or 403.
+fn is_auth_error(error: &str) -> bool { AUTH_ERROR.is_match(error) }
+
+// lets people reading the channel tell a quiet region from a gap in coverage.
+fn announcement(seq: &Sequence, region: &str, event: &str) -> Message {
+    let now = chrono::Utc::now();
+    let content = format!("Bridge {} region {} at {}", event, region, now.format("%Y-%m-%d %H:%M:%S UTC"));
+    seq.number(Message::system(content).at(now.timestamp()))
+}
+
+// custom queries can refer to the start of the subscription as {start} (timestamp) and {start_secs}.
+fn subscriptions(config: &Config, start: Timestamp) -> Vec<String> {
+    // floored, rounding up would also skip chat from the second after the one the subscription started in.
+    let start_secs = sieve::floor_secs(start).to_string();
+    if let Some(queries) = config.subscriptions() {
+        return queries
+            .iter()
+            .map(|query| query.replace("{start_secs}", &start_secs).replace("{start}", &start.to_string()))
+            .collect()
+    }
+
+    let floor = if config.low_channels() { "" } else { "t.channel_id > 2 AND " };
+    let mut queries = vec![
+        "SELECT * FROM claim_state".to_string(),
+        "SELECT * FROM empire_state".to_string(),
+        "SELECT * FROM player_username_state".to_string(),
+        format!(r"SELECT t.*
+                  FROM chat_message_state t
+                  WHERE {}t.timestamp > {}", floor, start_secs),
+        format!(r"SELECT t.*
+                  FROM user_moderation_state t
+                  WHERE t.created_time > '{}'", start),
+    ];
+    if config.announce_logins() || config.announce_logouts() {
+        queries.push("SELECT * FROM signed_in_player_state".to_string());
+    }
+    queries
+}
+
+async fn stopped(mut stop: watch::Receiver<bool>) {
+    let _ = stop.wait_for(|stop| *stop).await;
+}
+
+// fans the outbox out to the sinks until it closes or a Disconnect comes through, then lets them drain.
+pub async fn consume(mut rx: outbox::Receiver, config: watch::Receiver<Config>, sinks: Vec<Box<dyn Sink>>, seq: Sequence) {
+    let (outlets, tasks): (Vec<_>, Vec<_>) = sinks.into_iter().map(|sink| sink::spawn(sink, config.clone())).unzip();
+    let mut batch = Vec::new();
+    let mut deadline = Instant::now();
+    let mut not_before = Instant::now();
+    // a subscription can die without a disconnect, a region that goes quiet for too long is reported once.
+    let mut last_chat = Instant::now();
+    let mut alerted = false;
+
+    loop {
+        let stale_after = config.borrow().stale_after();
+        let stale_at = last_chat + stale_after.unwrap_or_default();
+        let msg = tokio::select! {
+            _ = tokio::time::sleep_until(deadline), if !batch.is_empty() => {
+                pace(&mut not_before, batch.len(), &config).await;
+                flush(&outlets, &mut batch).await;
+                continue
+            }
+            _ = tokio::time::sleep_until(stale_at), if stale_after.is_some() && !alerted => {
+                alerted = true;
+                let minutes = last_chat.elapsed().as_secs() / 60;
+                tracing::warn!(minutes, "no chat received, the subscription may have stalled");
+                batch.push(seq.number(Message::system(format!("No chat received in {} minutes", minutes))));
+                flush(&outlets, &mut batch).await;
+                continue
+            }
+            msg = rx.recv() => msg,
+        };
+
+        match msg {
+            None | Some(Message::Disconnect) => {
+                while let Some(msg) = rx.try_recv() {
+                    if !matches!(msg, Message::Disconnect) { batch.push(msg); }
+                }
+                // the backlog goes out unpaced, the sinks only have SHUTDOWN_TIMEOUT to deliver it.
+                flush(&outlets, &mut batch).await;
+                break
+            }
+            Some(msg) => {
+                if matches!(msg, Message::Chat { .. }) {
+                    last_chat = Instant::now();
+                    alerted = false;
+                }
+                if let Message::Chat { username, tag, content, channel, timestamp, .. } = msg.clone().plain() {
+                    tracing::debug!(channel = %channel.label(), username = %username, "bridging message");
+                    let config = config.borrow();
+                    if config.echo() {
+                        let time = config.format_time(timestamp.unwrap_or_else(|| chrono::Utc::now().timestamp()));
+                        println!("{} {}: {}", time, config.author(channel, &username, tag.as_deref()), content);
+                    }
+                }
+                let (window, size) = {
+                    let config = config.borrow();
+                    (config.batch_window(), config.batch_size())
+                };
+
+                if batch.is_empty() { deadline = Instant::now() + window; }
+                batch.push(msg);
+                if batch.len() >= size {
+                    pace(&mut not_before, batch.len(), &config).await;
+                    flush(&outlets, &mut batch).await;
+                }
+            }
+        }
+    }
+
+    // closing the outlets lets every sink finish what it has queued before its task ends.
+    drop(outlets);
+    let finished = async { for task in tasks { let _ = task.await; } };
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, finished).await.is_err() {
+        tracing::warn!(timeout_secs = SHUTDOWN_TIMEOUT.as_secs(), "sinks did not finish in time, exiting anyway");
+    }
+}
+
+// waits until the previous flush is paid for at send_rate, then books this one. the outbox buffers meanwhile.
+async fn pace(not_before: &mut Instant, messages: usize, config: &watch::Receiver<Config>) {
+    let Some(interval) = config.borrow().send_interval() else { return };
+    tokio::time::sleep_until(*not_before).await;
+    *not_before = Instant::now() + interval * messages as u32;
+}
+
+// a full outlet either holds up the flush or loses its oldest batches, like the outbox does for the sieve.
+async fn flush(outlets: &[Outlet], batch: &mut Vec<Message>) {
+    if batch.is_empty() { return }
+
+    let batch: Batch = std::mem::take(batch).into();
+    for outlet in outlets {
+        outlet.send(batch.clone()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spots_refused_tokens() {
+        assert!(is_auth_error("websocket handshake failed: HTTP error: 401 Unauthorized"));
+        assert!(is_auth_error("http status 403"));
+        assert!(is_auth_error("Invalid Token"));
+        assert!(!is_auth_error("failed to connect to 10.0.0.1:14013"));
+        assert!(!is_auth_error("request 4010 timed out after 403ms"));
+    }
+}
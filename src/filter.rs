@@ -0,0 +1,254 @@
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, Instant};
+use regex::Regex;
+
+// entries prefixed with `re:` are regexes, anything else is matched as a case-insensitive substring.
+#[derive(Clone, Debug, Default)]
+pub struct Blocklist {
+    keywords: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Blocklist {
+    pub fn compile(entries: &[String]) -> Result<Self> {
+        let mut blocklist = Self::default();
+
+        for entry in entries.iter().filter(|entry| !entry.is_empty()) {
+            match entry.strip_prefix("re:") {
+                Some(pattern) => {
+                    let regex = Regex::new(pattern)
+                        .map_err(|e| anyhow!("blocklist pattern {:?} is not a valid regex: {}", pattern, e))?;
+                    blocklist.patterns.push(regex);
+                }
+                None => blocklist.keywords.push(entry.to_lowercase()),
+            }
+        }
+
+        Ok(blocklist)
+    }
+
+    pub fn matches(&self, content: &str) -> bool {
+        if !self.keywords.is_empty() {
+            let content = content.to_lowercase();
+            if self.keywords.iter().any(|keyword| content.contains(keyword)) { return true }
+        }
+        self.patterns.iter().any(|pattern| pattern.is_match(content))
+    }
+}
+
+// whole words matched case-insensitively, longest first so a longer word wins over a word it starts with.
+#[derive(Clone, Debug, Default)]
+pub struct Profanity {
+    pattern: Option<Regex>,
+}
+
+impl Profanity {
+    pub fn compile(words: &[String]) -> Result<Self> {
+        let mut words = words.iter().map(|word| word.trim()).filter(|word| !word.is_empty()).collect::<Vec<_>>();
+        if words.is_empty() { return Ok(Self::default()) }
+        words.sort_by_key(|word| std::cmp::Reverse(word.chars().count()));
+
+        let alternatives = words.iter().map(|word| regex::escape(word)).collect::<Vec<_>>().join("|");
+        let pattern = Regex::new(&format!(r"(?i)\b(?:{})\b", alternatives))
+            .map_err(|e| anyhow!("profanity list can not be compiled: {}", e))?;
+        Ok(Self { pattern: Some(pattern) })
+    }
+
+    // every matched word becomes as many asterisks as it has characters.
+    pub fn mask(&self, content: &str) -> String {
+        let Some(pattern) = &self.pattern else { return content.to_string() };
+        pattern.replace_all(content, |captures: &regex::Captures| "*".repeat(captures[0].chars().count())).into_owned()
+    }
+}
+
+#[derive(PartialEq)]
+pub enum Admit {
+    Pass,
+    // the first message over the limit, to be replaced by a single notice.
+    Notify,
+    Drop,
+}
+
+struct Bucket {
+    tokens:   f64,
+    updated:  Instant,
+    notified: bool,
+}
+
+// a token bucket per username, holding `messages` tokens that refill over `per`.
+#[derive(Default)]
+pub struct Throttle {
+    buckets: HashMap<String, Bucket>,
+    pruned:  Option<Instant>,
+}
+
+impl Throttle {
+    pub fn admit(&mut self, username: &str, messages: u32, per: Duration) -> Admit {
+        let now = Instant::now();
+        let capacity = messages as f64;
+        self.prune(now, per);
+
+        let bucket = self.buckets.entry(username.to_string()).or_insert(Bucket {
+            tokens: capacity, updated: now, notified: false,
+        });
+
+        let refill = now.duration_since(bucket.updated).as_secs_f64() / per.as_secs_f64().max(f64::EPSILON);
+        bucket.tokens = (bucket.tokens + refill * capacity).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.notified = false;
+            Admit::Pass
+        } else if !bucket.notified {
+            bucket.notified = true;
+            Admit::Notify
+        } else {
+            Admit::Drop
+        }
+    }
+
+    // a bucket idle for a whole period is full again, so forgetting it changes nothing.
+    fn prune(&mut self, now: Instant, per: Duration) {
+        if self.pruned.is_some_and(|pruned| now.duration_since(pruned) < per) { return }
+        self.buckets.retain(|_, bucket| now.duration_since(bucket.updated) < per);
+        self.pruned = Some(now);
+    }
+}
+
+// remembers the last `window` chat rows, so a row delivered again after a resubscription is bridged once.
+#[derive(Default)]
+pub struct Recent {
+    seen:  HashSet<u64>,
+    order: VecDeque<u64>,
+}
+
+impl Recent {
+    pub fn is_repeat(&mut self, row: &impl Hash, window: usize) -> bool {
+        if window == 0 { return false }
+
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        let key = hasher.finish();
+
+        if !self.seen.insert(key) { return true }
+        self.order.push_back(key);
+
+        while self.order.len() > window {
+            if let Some(oldest) = self.order.pop_front() { self.seen.remove(&oldest); }
+        }
+        false
+    }
+}
+
+// moderation events already announced, everything up to the high-water mark is skipped, across restarts too.
+// only the ids of the events at the mark itself are remembered, as several may share its timestamp.
+#[derive(Default)]
+pub struct Watermark {
+    path:  Option<PathBuf>,
+    floor: i64,
+    mark:  i64,
+    seen:  HashSet<u64>,
+}
+
+impl Watermark {
+    pub fn load(path: Option<&str>) -> Self {
+        let Some(path) = path.map(PathBuf::from) else { return Self::default() };
+        let floor = match std::fs::read_to_string(&path) {
+            Ok(content) => content.trim().parse().unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), error = %e, "ignoring unreadable moderation watermark");
+                0
+            }),
+            Err(_) => 0,
+        };
+        Self { path: Some(path), floor, mark: floor, seen: HashSet::new() }
+    }
+
+    // whether the event was announced before, recording it if not. `created` is in micros since the epoch.
+    pub fn is_repeat(&mut self, id: u64, created: i64) -> bool {
+        if created <= self.floor || created < self.mark { return true }
+        if created > self.mark {
+            self.seen.clear();
+            self.mark = created;
+            self.persist();
+        }
+        !self.seen.insert(id)
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.path else { return };
+        let tmp = path.with_extension("tmp");
+        let result = std::fs::write(&tmp, self.mark.to_string()).and_then(|_| std::fs::rename(&tmp, path));
+        if let Err(e) = result { tracing::error!(path = %path.display(), error = %e, "failed to persist moderation watermark"); }
+    }
+}
+
+// drops control characters but the line break, and the invisible characters used to slip words past the filters.
+// zero-width (non-)joiners stay, emoji sequences and several scripts need them.
+pub fn sanitize(text: &str) -> String {
+    text.chars()
+        .filter(|&c| c == '\n' || !c.is_control())
+        .filter(|c| !matches!(c, '\u{200B}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}' | '\u{180E}'))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profanity(words: &[&str]) -> Profanity {
+        Profanity::compile(&words.iter().map(|word| word.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn leaves_words_inside_longer_words() {
+        let profanity = profanity(&["ass"]);
+        assert_eq!(profanity.mask("a classic assessment"), "a classic assessment");
+        assert_eq!(profanity.mask("you ass."), "you ***.");
+    }
+
+    #[test]
+    fn prefers_the_longest_word() {
+        let profanity = profanity(&["bad", "bad word"]);
+        assert_eq!(profanity.mask("a bad word here"), "a ******** here");
+        assert_eq!(profanity.mask("just bad"), "just ***");
+    }
+
+    #[test]
+    fn ignores_case() {
+        assert_eq!(profanity(&["darn"]).mask("DARN it, Darn"), "**** it, ****");
+    }
+
+    #[test]
+    fn bounds_words_outside_ascii() {
+        let profanity = profanity(&["schön", "ö"]);
+        // masked per character, not per byte.
+        assert_eq!(profanity.mask("so schön!"), "so *****!");
+        assert_eq!(profanity.mask("schöner"), "schöner");
+        assert_eq!(profanity.mask("ö ö"), "* *");
+    }
+
+    #[test]
+    fn matches_words_literally() {
+        let profanity = profanity(&["a.b", " "]);
+        assert_eq!(profanity.mask("axb"), "axb");
+        assert_eq!(profanity.mask("a.b"), "***");
+        assert_eq!(Profanity::default().mask("anything"), "anything");
+    }
+
+    #[test]
+    fn skips_everything_up_to_the_mark() {
+        let mut watermark = Watermark::default();
+        assert!(!watermark.is_repeat(1, 5));
+        assert!(!watermark.is_repeat(2, 5));
+        assert!(!watermark.is_repeat(3, 7));
+        // a reconnect delivers the same events again.
+        assert!(watermark.is_repeat(1, 5));
+        assert!(watermark.is_repeat(3, 7));
+        assert!(!watermark.is_repeat(4, 7));
+        assert_eq!(watermark.seen.len(), 2);
+    }
+}
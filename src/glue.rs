@@ -1,40 +1,1154 @@
-use anyhow::Result;
-use std::path::Path;
+use anyhow::{anyhow, ensure, Result};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use serde;
+use chrono::{DateTime, SecondsFormat};
+use chrono::format::{Item, StrftimeItems};
+use chrono_tz::Tz;
 use bindings::sdk::{DbConnectionBuilder, __codegen::SpacetimeModule};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::watch;
+use reqwest::Url;
+use crate::message::Channel;
+use crate::filter::{Blocklist, Profanity};
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct Config {
-    webhook_url: String,
+const MAX_BACKFILL_SECONDS: u64 = 60 * 60;
+const REQUIRED_TABLES: [&str; 5] = [
+    "claim_state", "empire_state", "player_username_state", "chat_message_state", "user_moderation_state",
+];
+const HTTP_BODY: [&str; 3] = ["username", "content", "channel"];
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl <T> Default for OneOrMany<T> {
+    fn default() -> Self { Self::Many(Vec::new()) }
+}
+
+impl <T> OneOrMany<T> {
+    fn as_slice(&self) -> &[T] {
+        match self {
+            Self::One(value) => std::slice::from_ref(value),
+            Self::Many(values) => values.as_slice(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Content,
+    Embed,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Colors {
+    region:          String,
+    claim:           String,
+    empire_public:   String,
+    empire_internal: String,
+    moderation:      String,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            region:          "#3498db".to_string(),
+            claim:           "#2ecc71".to_string(),
+            empire_public:   "#9b59b6".to_string(),
+            empire_internal: "#e67e22".to_string(),
+            moderation:      "#e74c3c".to_string(),
+        }
+    }
+}
+
+impl Colors {
+    fn get(&self, channel: Channel) -> &str {
+        match channel {
+            Channel::Region | Channel::Other(_) => &self.region,
+            Channel::Claim          => &self.claim,
+            Channel::EmpirePublic   => &self.empire_public,
+            Channel::EmpireInternal => &self.empire_internal,
+            Channel::Moderation     => &self.moderation,
+        }
+    }
+}
+
+// discord thread ids per channel, for webhooks of a forum channel. empty posts in the channel itself.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Threads {
+    region:          String,
+    claim:           String,
+    empire_public:   String,
+    empire_internal: String,
+    moderation:      String,
+}
+
+impl Threads {
+    fn get(&self, channel: Channel) -> &str {
+        match channel {
+            Channel::Region | Channel::Other(_) => &self.region,
+            Channel::Claim          => &self.claim,
+            Channel::EmpirePublic   => &self.empire_public,
+            Channel::EmpireInternal => &self.empire_internal,
+            Channel::Moderation     => &self.moderation,
+        }
+    }
+}
+
+// how the author of a message is shown, with named placeholders for the parts that vary.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Templates {
+    chat:   String,
+    claim:  String,
+    empire: String,
+    region: String,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self {
+            chat:   "{username}".to_string(),
+            claim:  "{username} [{claim}]".to_string(),
+            empire: "{username} [{empire}]".to_string(),
+            region: "{username} [{region}]".to_string(),
+        }
+    }
+}
+
+impl Templates {
+    fn all(&self) -> [(&'static str, &str, &'static [&'static str]); 4] {
+        [
+            ("chat", &self.chat, &["username"]),
+            ("claim", &self.claim, &["username", "claim"]),
+            ("empire", &self.empire, &["username", "empire"]),
+            ("region", &self.region, &["username", "region"]),
+        ]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Oversize {
+    Split,
+    Truncate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagPosition {
+    Prefix,
+    Suffix,
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    Block,
+    DropOldest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    Discord,
+    Slack,
+    Telegram,
+    File,
+    Api,
+    WebSocket,
+    Sqlite,
+    Http,
+    Redis,
+    Nats,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    None,
+    Daily,
+    Size,
+}
+
+// keeps secrets out of `{:?}` output, which may end up in shipped logs.
+#[derive(Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str { &self.0 }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("\"***\"") }
+}
+
+// command line flags, applied over the file and the environment on every (re)load.
+#[derive(Clone, Default)]
+pub struct Overrides {
+    pub webhook_url: Option<String>,
+    pub dry_run:     bool,
+    pub quiet:       bool,
+}
+
+// one more region bridged by the same process, anything left empty is taken from the top level.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Region {
     cluster_url: String,
     region:      String,
-    token:       String,
+    token:       Secret,
+    webhook_url: OneOrMany<String>,
+}
+
+// what a raw chat channel id stands for, in case the game renumbers its channels. the label, when set,
+// replaces the name of the kind wherever it is shown.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct ChannelId {
+    id:    i32,
+    kind:  Channel,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    label: String,
+}
+
+// claim or empire chat of the named claim or empire goes to its own webhook instead of the region's.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct Route {
+    name:        String,
+    webhook_url: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Config {
+    webhook_url:  OneOrMany<String>,
+    cluster_url:  String,
+    region:       String,
+    token:        Secret,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    token_file:   String,
+    max_attempts: u32,
+    batch_window_ms: u64,
+    batch_size:   usize,
+    send_rate:    u32,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    queue_path:   String,
+    queue_capacity: usize,
+    format:       Format,
+    colors:       Colors,
+    threads:      Threads,
+    channel_prefix: String,
+    webhook_username: String,
+    webhook_avatar_url: String,
+    player_identity: bool,
+    oversize:     Oversize,
+    unresolved_name: String,
+    unresolved_player: String,
+    empty_username: String,
+    drop_empty_username: bool,
+    moderation_resolve_secs: u64,
+    sink:         OneOrMany<SinkKind>,
+    telegram_bot_token: Secret,
+    telegram_chat_id: String,
+    file_path:    String,
+    file_rotation: Rotation,
+    file_max_bytes: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    sqlite_path:  String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    http_url:     String,
+    http_method:  String,
+    http_content_type: String,
+    http_body:    String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    http_headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Secret::is_empty")]
+    redis_url:    Secret,
+    redis_channel: String,
+    #[serde(skip_serializing_if = "Secret::is_empty")]
+    nats_url:     Secret,
+    nats_subject: String,
+    nats_jetstream: bool,
+    blocklist:    Vec<String>,
+    log_dropped:  bool,
+    muted_users:  Vec<String>,
+    min_content_length: usize,
+    sanitize:     bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    profanity:    Vec<String>,
+    rate_limit:   u32,
+    rate_limit_secs: u64,
+    dedup_window: usize,
+    moderation_issuer: bool,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    moderation_webhook_url: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    moderation_policies: Vec<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    moderation_state_path: String,
+    moderation_collapse: usize,
+    channels:     Vec<Channel>,
+    bridge_unknown_channels: bool,
+    low_channels: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    channel_ids:  Vec<ChannelId>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    routes:       Vec<Route>,
+    backfill_seconds: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    subscriptions: Vec<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    metrics_addr: String,
+    ready_max_silence_secs: u64,
+    stale_chat_minutes: u64,
+    show_timestamp: bool,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    timestamp_format: String,
+    timezone:     String,
+    announce_logins: bool,
+    announce_logouts: bool,
+    announce_claims: bool,
+    announce_connection: bool,
+    message_capacity: usize,
+    overflow:     Overflow,
+    update_capacity: usize,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    api_addr:     String,
+    api_buffer:   usize,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    websocket_addr: String,
+    websocket_max_clients: usize,
+    dry_run:      bool,
+    echo:         bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    regions:      Vec<Region>,
+    templates:    Templates,
+    tag_position: TagPosition,
+    username_prefix: String,
+    username_suffix: String,
+    region_label: String,
+    breaker_failures: u32,
+    breaker_cooldown_secs: u64,
+    connect_timeout_secs: u64,
+    request_timeout_secs: u64,
+    connect_attempts: u32,
+    fatal_auth_errors: bool,
+    #[serde(skip)]
+    blocked:      Blocklist,
+    #[serde(skip)]
+    profane:      Profanity,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            webhook_url:  OneOrMany::One(String::new()),
+            cluster_url:  String::new(),
+            region:       String::new(),
+            token:        Secret::default(),
+            token_file:   String::new(),
+            max_attempts: 3,
+            batch_window_ms: 500,
+            batch_size:   1,
+            send_rate:    0,
+            queue_path:   String::new(),
+            queue_capacity: 10_000,
+            format:       Format::Content,
+            colors:       Colors::default(),
+            threads:      Threads::default(),
+            channel_prefix: "[{channel}] ".to_string(),
+            webhook_username: String::new(),
+            webhook_avatar_url: String::new(),
+            player_identity: false,
+            oversize:     Oversize::Split,
+            unresolved_name: "{id}".to_string(),
+            unresolved_player: "Player #{id}".to_string(),
+            empty_username: "Server".to_string(),
+            drop_empty_username: false,
+            moderation_resolve_secs: 0,
+            sink:         OneOrMany::One(SinkKind::Discord),
+            telegram_bot_token: Secret::default(),
+            telegram_chat_id: String::new(),
+            file_path:    String::new(),
+            file_rotation: Rotation::None,
+            file_max_bytes: 10 * 1024 * 1024,
+            sqlite_path:  String::new(),
+            http_url:     String::new(),
+            http_method:  "POST".to_string(),
+            http_content_type: "application/json".to_string(),
+            http_body:    r#"{"username":"{username}","content":"{content}","channel":"{channel}"}"#.to_string(),
+            http_headers: BTreeMap::new(),
+            redis_url:    Secret::default(),
+            redis_channel: "chatbridge".to_string(),
+            nats_url:     Secret::default(),
+            nats_subject: "chatbridge".to_string(),
+            nats_jetstream: false,
+            blocklist:    Vec::new(),
+            log_dropped:  false,
+            muted_users:  Vec::new(),
+            min_content_length: 1,
+            sanitize:     true,
+            profanity:    Vec::new(),
+            rate_limit:   0,
+            rate_limit_secs: 10,
+            dedup_window: 256,
+            moderation_issuer: false,
+            moderation_webhook_url: String::new(),
+            moderation_policies: Vec::new(),
+            moderation_state_path: String::new(),
+            moderation_collapse: 0,
+            channels:     vec![Channel::Region, Channel::Claim, Channel::EmpirePublic, Channel::EmpireInternal],
+            bridge_unknown_channels: false,
+            low_channels: false,
+            channel_ids:  Vec::new(),
+            routes:       Vec::new(),
+            backfill_seconds: 0,
+            subscriptions: Vec::new(),
+            metrics_addr: String::new(),
+            ready_max_silence_secs: 0,
+            stale_chat_minutes: 0,
+            show_timestamp: false,
+            timestamp_format: String::new(),
+            timezone:     "UTC".to_string(),
+            announce_logins: false,
+            announce_logouts: false,
+            announce_claims: false,
+            announce_connection: false,
+            message_capacity: 1024,
+            overflow:     Overflow::DropOldest,
+            update_capacity: 256,
+            api_addr:     String::new(),
+            api_buffer:   100,
+            websocket_addr: String::new(),
+            websocket_max_clients: 64,
+            dry_run:      false,
+            echo:         true,
+            regions:      Vec::new(),
+            templates:    Templates::default(),
+            tag_position: TagPosition::Suffix,
+            username_prefix: String::new(),
+            username_suffix: String::new(),
+            region_label: String::new(),
+            breaker_failures: 5,
+            breaker_cooldown_secs: 60,
+            connect_timeout_secs: 10,
+            request_timeout_secs: 10,
+            connect_attempts: 0,
+            fatal_auth_errors: true,
+            blocked:      Blocklist::default(),
+            profane:      Profanity::default(),
+        }
+    }
 }
 
 impl Config {
-    fn new() -> Self {
-        Self { webhook_url: String::new(), cluster_url: String::new(), region: String::new(), token: String::new() }
+
+    pub fn from(path: &str, overrides: &Overrides) -> Result<Self> {
+        if !Path::new(path).exists() {
+            let config = Config::default();
+            let content = match FileFormat::of(path) {
+                FileFormat::Json => serde_json::to_string_pretty(&config)?,
+                FileFormat::Toml => toml::to_string_pretty(&config)?,
+            };
+            // a read-only filesystem, as in most containers, still runs from the defaults and the environment.
+            if let Err(e) = std::fs::write(path, content) {
+                tracing::warn!(error = %e, path, "could not write a default config, running without one");
+                return config.finish(overrides)
+            }
+        }
+        Self::read(path, overrides)
+    }
+
+    fn read(path: &str, overrides: &Overrides) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let config: Self = match FileFormat::of(path) {
+            FileFormat::Json => serde_json::from_str(&content)?,
+            FileFormat::Toml => toml::from_str(&content)?,
+        };
+        config.finish(overrides)
+    }
+
+    fn finish(mut self, overrides: &Overrides) -> Result<Self> {
+        self.overlay_env();
+        self.overlay(overrides);
+        self.read_token_file()?;
+        self.blocked = Blocklist::compile(&self.blocklist)?;
+        self.profane = Profanity::compile(&self.profanity)?;
+        Ok(self)
+    }
+
+    // re-reads token_file before every connection attempt, so a rotated token needs no restart.
+    pub fn refresh_token(&mut self) -> Result<()> { self.read_token_file() }
+
+    pub fn token(&self) -> &Secret { &self.token }
+
+    fn read_token_file(&mut self) -> Result<()> {
+        if self.token_file.is_empty() { return Ok(()) }
+
+        let token = std::fs::read_to_string(&self.token_file)
+            .map_err(|e| anyhow!("failed to read token_file {}: {}", self.token_file, e))?;
+        self.token = Secret(token.trim_end().to_string());
+        Ok(())
+    }
+
+    // everything but the settings a running connection or sink was built from is taken from the reloaded config.
+    fn reload(&mut self, mut other: Config) {
+        if self.cluster_url != other.cluster_url || self.region != other.region || self.sink != other.sink
+            || self.queue_path != other.queue_path || self.queue_capacity != other.queue_capacity
+            || self.file_path != other.file_path || self.subscriptions != other.subscriptions
+            || self.metrics_addr != other.metrics_addr || self.ready_max_silence_secs != other.ready_max_silence_secs
+            || self.message_capacity != other.message_capacity || self.update_capacity != other.update_capacity
+            || self.overflow != other.overflow || self.api_addr != other.api_addr || self.api_buffer != other.api_buffer
+            || self.websocket_addr != other.websocket_addr || self.websocket_max_clients != other.websocket_max_clients
+            || self.dry_run != other.dry_run || self.regions != other.regions
+            || self.connect_timeout_secs != other.connect_timeout_secs || self.request_timeout_secs != other.request_timeout_secs
+            || self.sqlite_path != other.sqlite_path || self.moderation_state_path != other.moderation_state_path
+        {
+            tracing::warn!("connection or sink settings changed, restart required to apply them!");
+        }
+
+        other.cluster_url = std::mem::take(&mut self.cluster_url);
+        other.region = std::mem::take(&mut self.region);
+        other.sink = std::mem::take(&mut self.sink);
+        other.queue_path = std::mem::take(&mut self.queue_path);
+        other.queue_capacity = self.queue_capacity;
+        other.file_path = std::mem::take(&mut self.file_path);
+        other.subscriptions = std::mem::take(&mut self.subscriptions);
+        other.metrics_addr = std::mem::take(&mut self.metrics_addr);
+        other.ready_max_silence_secs = self.ready_max_silence_secs;
+        other.message_capacity = self.message_capacity;
+        other.update_capacity = self.update_capacity;
+        other.overflow = self.overflow;
+        other.api_addr = std::mem::take(&mut self.api_addr);
+        other.api_buffer = self.api_buffer;
+        other.websocket_addr = std::mem::take(&mut self.websocket_addr);
+        other.websocket_max_clients = self.websocket_max_clients;
+        other.dry_run = self.dry_run;
+        other.regions = std::mem::take(&mut self.regions);
+        other.connect_timeout_secs = self.connect_timeout_secs;
+        other.request_timeout_secs = self.request_timeout_secs;
+        other.sqlite_path = std::mem::take(&mut self.sqlite_path);
+        other.moderation_state_path = std::mem::take(&mut self.moderation_state_path);
+        *self = other;
+    }
+
+    pub fn watch(self, path: &str, overrides: Overrides) -> Result<(RecommendedWatcher, watch::Receiver<Config>)> {
+        let (tx, rx) = watch::channel(self);
+
+        let file = PathBuf::from(path);
+        let name = file.file_name().map(|name| name.to_os_string());
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let path = path.to_string();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else { return };
+            if !(event.kind.is_modify() || event.kind.is_create()) { return }
+            if !event.paths.iter().any(|p| p.file_name() == name.as_deref()) { return }
+
+            match Config::read(&path, &overrides).and_then(|config| config.validate().map(|_| config)) {
+                Ok(config) => tx.send_modify(|current| current.reload(config)),
+                Err(e) => tracing::error!(error = %e, "failed to reload config, keeping previous"),
+            }
+        })?;
+
+        // watch the directory rather than the file, editors tend to replace files on save.
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        Ok((watcher, rx))
     }
 
-    pub fn from(path: &str) -> Result<Self> {
-        let path = Path::new(path);
-        if !path.exists() {
-            let config = Config::new();
-            let content = serde_json::to_string_pretty(&config)?;
-            std::fs::write(path, content)?;
-            Ok(config)
-        } else {
-            let content = std::fs::read(path)?;
-            let config = serde_json::from_slice(&content)?;
-            Ok(config)
+    fn overlay_env(&mut self) {
+        if let Some(value) = env("CHATBRIDGE_WEBHOOK_URL") { self.webhook_url = OneOrMany::One(value); }
+        if let Some(value) = env("CHATBRIDGE_CLUSTER_URL") { self.cluster_url = value; }
+        if let Some(value) = env("CHATBRIDGE_REGION") { self.region = value; }
+        if let Some(value) = env("CHATBRIDGE_TOKEN") { self.token = Secret(value); }
+        if let Some(value) = env("CHATBRIDGE_TOKEN_FILE") { self.token_file = value; }
+        if let Some(value) = env("CHATBRIDGE_TELEGRAM_BOT_TOKEN") { self.telegram_bot_token = Secret(value); }
+        if let Some(value) = env("CHATBRIDGE_REDIS_URL") { self.redis_url = Secret(value); }
+        if let Some(value) = env("CHATBRIDGE_NATS_URL") { self.nats_url = Secret(value); }
+    }
+
+    fn overlay(&mut self, overrides: &Overrides) {
+        if let Some(value) = &overrides.webhook_url { self.webhook_url = OneOrMany::One(value.clone()); }
+        if overrides.dry_run { self.dry_run = true; }
+        if overrides.quiet { self.echo = false; }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let regions = self.regions();
+        if regions.len() > 1 {
+            let sinks = self.sinks();
+            ensure!(
+                !sinks.contains(&SinkKind::Api) && !sinks.contains(&SinkKind::WebSocket),
+                "the api and websocket sinks cannot be shared by several regions"
+            );
+        }
+
+        for config in &regions {
+            config.validate_region().map_err(|e| anyhow!("region {}: {}", config.region, e))?;
+        }
+        Ok(())
+    }
+
+    fn validate_region(&self) -> Result<()> {
+        ensure!(self.echo || !self.sinks().is_empty(), "no sink is configured and echo is off, nothing would be bridged");
+
+        let url = Url::parse(&self.cluster_url)
+            .map_err(|e| anyhow!("cluster_url is not a valid URI: {}", e))?;
+        ensure!(
+            matches!(url.scheme(), "ws" | "wss" | "http" | "https"),
+            "cluster_url must use ws://, wss://, http:// or https://, not {}://", url.scheme()
+        );
+        // plain ws:// and http:// send the token in the clear, fine for a test cluster on this machine only.
+        let local = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+        if matches!(url.scheme(), "ws" | "http") && !local {
+            tracing::warn!(cluster_url = %url, "cluster_url is not encrypted, use wss:// or https:// outside of local testing");
+        }
+
+        for webhook_url in self.webhook_url.as_slice().iter().filter(|url| !url.is_empty()) {
+            let url = Url::parse(webhook_url)
+                .map_err(|e| anyhow!("webhook_url is not a valid URL: {}", e))?;
+            ensure!(url.scheme() == "https", "webhook_url must use https://, not {}://", url.scheme());
+            ensure!(
+                is_discord_webhook(&url) || is_slack_webhook(&url),
+                "webhook_url must point at a discord (https://discord.com/api/webhooks/...) \
+                 or slack (https://hooks.slack.com/...) webhook"
+            );
+        }
+
+        if !self.moderation_webhook_url.is_empty() {
+            let url = Url::parse(&self.moderation_webhook_url)
+                .map_err(|e| anyhow!("moderation_webhook_url is not a valid URL: {}", e))?;
+            ensure!(
+                url.scheme() == "https" && is_discord_webhook(&url),
+                "moderation_webhook_url must point at a discord webhook (https://discord.com/api/webhooks/...)"
+            );
+        }
+
+        if !self.subscriptions.is_empty() {
+            for table in REQUIRED_TABLES {
+                ensure!(
+                    self.subscriptions.iter().any(|query| query.contains(table)),
+                    "subscriptions must still subscribe to {}", table
+                );
+            }
+        }
+
+        if self.sinks().contains(&SinkKind::Telegram) {
+            ensure!(!self.telegram_bot_token.is_empty(), "telegram_bot_token is required for the telegram sink");
+            ensure!(!self.telegram_chat_id.is_empty(), "telegram_chat_id is required for the telegram sink");
+        }
+
+        if self.sinks().contains(&SinkKind::Api) {
+            ensure!(!self.api_addr.is_empty(), "api_addr is required for the api sink (e.g. \"127.0.0.1:8080\")");
+        }
+
+        if self.sinks().contains(&SinkKind::WebSocket) {
+            ensure!(!self.websocket_addr.is_empty(), "websocket_addr is required for the websocket sink (e.g. \"127.0.0.1:8081\")");
+        }
+
+        if self.sinks().contains(&SinkKind::File) {
+            ensure!(!self.file_path.is_empty(), "file_path is required for the file sink (use \"-\" for stdout)");
+        }
+
+        ensure!(
+            !StrftimeItems::new(&self.timestamp_format).any(|item| matches!(item, Item::Error)),
+            "timestamp_format {:?} is not a valid strftime format", self.timestamp_format
+        );
+        self.timezone.parse::<Tz>()
+            .map_err(|_| anyhow!("timezone {:?} is not an IANA timezone like \"Europe/Berlin\"", self.timezone))?;
+
+        if self.sinks().contains(&SinkKind::Sqlite) {
+            ensure!(!self.sqlite_path.is_empty(), "sqlite_path is required for the sqlite sink");
+        }
+
+        if self.sinks().contains(&SinkKind::Redis) {
+            ensure!(!self.redis_url.is_empty(), "redis_url is required for the redis sink (e.g. \"redis://127.0.0.1/\")");
+            ensure!(!self.redis_channel.is_empty(), "redis_channel must not be empty");
+        }
+
+        if self.sinks().contains(&SinkKind::Nats) {
+            ensure!(!self.nats_url.is_empty(), "nats_url is required for the nats sink (e.g. \"nats://127.0.0.1:4222\")");
+            ensure!(
+                !self.nats_subject.is_empty() && !self.nats_subject.contains(['*', '>', ' ']),
+                "nats_subject must be a subject without wildcards or spaces, not {:?}", self.nats_subject
+            );
+        }
+
+        if self.sinks().contains(&SinkKind::Http) {
+            ensure!(!self.http_url.is_empty(), "http_url is required for the http sink");
+            let url = Url::parse(&self.http_url).map_err(|e| anyhow!("http_url is not a valid URL: {}", e))?;
+            ensure!(matches!(url.scheme(), "http" | "https"), "http_url must use http:// or https://, not {}://", url.scheme());
+            reqwest::Method::from_bytes(self.http_method.to_uppercase().as_bytes())
+                .map_err(|_| anyhow!("http_method {:?} is not a valid HTTP method", self.http_method))?;
+            if let Some(unknown) = placeholders(&self.http_body).find(|placeholder| !HTTP_BODY.contains(placeholder)) {
+                return Err(anyhow!("http_body uses unknown placeholder {{{}}}, expected one of {:?}", unknown, HTTP_BODY));
+            }
+        }
+
+        for (name, template, known) in self.templates.all() {
+            if let Some(unknown) = placeholders(template).find(|placeholder| !known.contains(placeholder)) {
+                return Err(anyhow!("templates.{} uses unknown placeholder {{{}}}, expected one of {:?}", name, unknown, known));
+            }
+        }
+
+        for route in &self.routes {
+            let url = Url::parse(&route.webhook_url)
+                .map_err(|e| anyhow!("routes entry for {:?} has no valid webhook_url: {}", route.name, e))?;
+            ensure!(
+                url.scheme() == "https" && is_discord_webhook(&url),
+                "routes entry for {:?} must point at a discord webhook (https://discord.com/api/webhooks/...)", route.name
+            );
+        }
+
+        for entry in &self.channel_ids {
+            ensure!(
+                !matches!(entry.kind, Channel::Moderation | Channel::Other(_)),
+                "channel_ids entry for {} must be of kind region, claim, empire_public or empire_internal", entry.id
+            );
         }
+
+        for channel in Channel::ALL {
+            let thread = self.threads.get(channel);
+            ensure!(
+                thread.chars().all(|c| c.is_ascii_digit()),
+                "threads entry for {:?} must be a discord thread id, not {:?}", channel, thread
+            );
+            ensure!(
+                parse_color(self.colors.get(channel)).is_some(),
+                "colors entry for {:?} must be a hex color like #3498db", channel
+            );
+        }
+
+        Ok(())
     }
 
     pub fn is_empty(&self) -> bool {
-        self.cluster_url.is_empty() || self.region.is_empty() || self.token.is_empty()
+        self.regions().iter().any(|config| {
+            config.cluster_url.is_empty() || config.region.is_empty() || config.token.is_empty()
+        })
+    }
+
+    // the config of every region this process bridges, each as if it were the only one.
+    pub fn regions(&self) -> Vec<Config> {
+        if self.regions.is_empty() { return vec![self.clone()] }
+
+        self.regions
+            .iter()
+            .map(|region| {
+                let mut config = self.clone();
+                config.regions.clear();
+                if !region.cluster_url.is_empty() { config.cluster_url = region.cluster_url.clone(); }
+                if !region.region.is_empty() { config.region = region.region.clone(); }
+                // the shared token_file must not replace a region's own token on the next refresh.
+                if !region.token.is_empty() {
+                    config.token = region.token.clone();
+                    config.token_file.clear();
+                }
+                if !region.webhook_url.as_slice().is_empty() { config.webhook_url = region.webhook_url.clone(); }
+                // every region replays its own queue, sharing one file would interleave them.
+                if !config.queue_path.is_empty() { config.queue_path = format!("{}.{}", config.queue_path, config.region); }
+                // two regions rotating one log would each rename it under the other, stdout is fine to share.
+                if !config.file_path.is_empty() && config.file_path != "-" {
+                    config.file_path = format!("{}.{}", config.file_path, config.region);
+                }
+                if !config.moderation_state_path.is_empty() {
+                    config.moderation_state_path = format!("{}.{}", config.moderation_state_path, config.region);
+                }
+                config
+            })
+            .collect()
+    }
+
+    // webhook urls are routed to the sink that speaks their format, judged by where they point.
+    pub fn webhook_urls(&self, kind: SinkKind) -> Vec<String> {
+        self.webhook_url
+            .as_slice()
+            .iter()
+            .filter(|url| Url::parse(url).is_ok_and(|url| match kind {
+                SinkKind::Discord => is_discord_webhook(&url),
+                SinkKind::Slack => is_slack_webhook(&url),
+                SinkKind::Telegram | SinkKind::File | SinkKind::Api | SinkKind::WebSocket | SinkKind::Sqlite | SinkKind::Http | SinkKind::Redis
+                    | SinkKind::Nats => false,
+            }))
+            .cloned()
+            .collect()
     }
 
-    pub fn webhook_url(&self) -> String { self.webhook_url.clone() }
+    // moderation notices share the regular discord webhooks unless they have their own.
+    // claim and empire chat with a route of its own skips the region webhooks, names compare case-insensitively.
+    pub fn chat_webhook_urls(&self, channel: Channel, tag: Option<&str>) -> Vec<String> {
+        let route = match (channel, tag) {
+            (Channel::Claim | Channel::EmpirePublic | Channel::EmpireInternal, Some(tag)) =>
+                self.routes.iter().find(|route| route.name.eq_ignore_ascii_case(tag)),
+            _ => None,
+        };
+        route.map_or_else(|| self.webhook_urls(SinkKind::Discord), |route| vec![route.webhook_url.clone()])
+    }
+
+    pub fn moderation_webhook_urls(&self) -> Vec<String> {
+        match non_empty(&self.moderation_webhook_url) {
+            Some(url) => vec![url.to_string()],
+            None => self.webhook_urls(SinkKind::Discord),
+        }
+    }
+
+    pub fn region(&self) -> &str { &self.region }
+
+    // the scheme lowercased and without a trailing slash, which the sdk would otherwise keep in every request path.
+    // tls is verified against the system trust store, a self-signed test cluster needs its certificate added
+    // there since the sdk offers no way to skip verification.
+    pub fn cluster_uri(&self) -> String {
+        match Url::parse(&self.cluster_url) {
+            Ok(url) => url.as_str().trim_end_matches('/').to_string(),
+            Err(_) => self.cluster_url.clone(),
+        }
+    }
+
+    pub fn max_attempts(&self) -> u32 { self.max_attempts.max(1) }
+
+    pub fn batch_window(&self) -> Duration { Duration::from_millis(self.batch_window_ms) }
+
+    pub fn batch_size(&self) -> usize { self.batch_size.max(1) }
+
+    // the time one message costs at `send_rate` messages per second across every sink, zero leaves sends unpaced.
+    pub fn send_interval(&self) -> Option<Duration> {
+        (self.send_rate > 0).then(|| Duration::from_secs(1) / self.send_rate)
+    }
+
+    pub fn queue_path(&self) -> Option<&str> { non_empty(&self.queue_path) }
+
+    pub fn queue_capacity(&self) -> usize { self.queue_capacity }
+
+    pub fn format(&self) -> Format { self.format }
+
+    // moderation notices already carry their own marker in place of a username.
+    pub fn channel_prefix(&self, channel: Channel, id: Option<i32>) -> String {
+        if channel == Channel::Moderation { return String::new() }
+        self.channel_prefix.replace("{channel}", &self.channel_label(channel, id))
+    }
+
+    pub fn webhook_username(&self) -> Option<&str> { non_empty(&self.webhook_username) }
+
+    pub fn webhook_avatar_url(&self) -> Option<&str> { non_empty(&self.webhook_avatar_url) }
+
+    pub fn player_identity(&self) -> bool { self.player_identity }
+
+    pub fn oversize(&self) -> Oversize { self.oversize }
+
+    pub fn unresolved_name(&self, id: u64) -> String { self.unresolved_name.replace("{id}", &id.to_string()) }
+
+    pub fn unresolved_player(&self, id: u64) -> String { self.unresolved_player.replace("{id}", &id.to_string()) }
+
+    // chat the game sends without a player, e.g. from npcs, goes out under this name unless it is dropped.
+    pub fn empty_username(&self) -> Option<&str> { (!self.drop_empty_username).then_some(&self.empty_username) }
+
+    // how long moderation of a player without a known name waits for the name to arrive, zero never waits.
+    pub fn moderation_resolve_wait(&self) -> Option<Duration> {
+        (self.moderation_resolve_secs > 0).then(|| Duration::from_secs(self.moderation_resolve_secs))
+    }
+
+    // more bans of one policy than this in a single update become one summary listing only the first few, zero never
+    // collapses.
+    pub fn moderation_collapse(&self) -> Option<usize> { (self.moderation_collapse > 0).then_some(self.moderation_collapse) }
+
+    pub fn sinks(&self) -> Vec<SinkKind> {
+        let mut sinks = Vec::new();
+        for kind in self.sink.as_slice() {
+            if !sinks.contains(kind) { sinks.push(*kind); }
+        }
+        sinks
+    }
+
+    pub fn telegram_bot_token(&self) -> &Secret { &self.telegram_bot_token }
+
+    pub fn telegram_chat_id(&self) -> &str { &self.telegram_chat_id }
+
+    pub fn file_path(&self) -> &str { &self.file_path }
+
+    pub fn file_rotation(&self) -> Rotation { self.file_rotation }
+
+    pub fn file_max_bytes(&self) -> u64 { self.file_max_bytes }
+
+    pub fn sqlite_path(&self) -> &str { &self.sqlite_path }
+
+    pub fn http_url(&self) -> &str { &self.http_url }
+
+    pub fn redis_url(&self) -> &Secret { &self.redis_url }
+
+    pub fn redis_channel(&self) -> &str { &self.redis_channel }
+
+    pub fn nats_url(&self) -> &Secret { &self.nats_url }
+
+    pub fn nats_subject(&self) -> &str { &self.nats_subject }
+
+    // publishes through jetstream and waits for the stream to acknowledge, instead of fire and forget.
+    pub fn nats_jetstream(&self) -> bool { self.nats_jetstream }
+
+    pub fn http_method(&self) -> reqwest::Method {
+        reqwest::Method::from_bytes(self.http_method.to_uppercase().as_bytes()).unwrap_or(reqwest::Method::POST)
+    }
+
+    // the content type and the custom headers, a custom Content-Type wins.
+    pub fn http_headers(&self) -> Vec<(String, String)> {
+        let mut headers = vec![("Content-Type".to_string(), self.http_content_type.clone())];
+        headers.retain(|(name, _)| !self.http_headers.keys().any(|custom| custom.eq_ignore_ascii_case(name)));
+        headers.extend(self.http_headers.iter().map(|(name, value)| (name.clone(), value.clone())));
+        headers
+    }
+
+    // values are escaped for json bodies, so a quote in chat can not break the payload.
+    pub fn http_body(&self, username: &str, content: &str, channel: &str) -> String {
+        let json = self.http_content_type.contains("json");
+        let escape = |value: &str| match json {
+            true => serde_json::to_string(value).map(|quoted| quoted[1..quoted.len() - 1].to_string()).unwrap_or_default(),
+            false => value.to_string(),
+        };
+        render(&self.http_body, &[("username", &escape(username)), ("content", &escape(content)), ("channel", &escape(channel))])
+    }
+
+    pub fn is_blocked(&self, content: &str) -> bool { self.blocked.matches(content) }
+
+    // unlike the blocklist, profanity only masks the words and still bridges the message.
+    pub fn mask(&self, content: &str) -> String { self.profane.mask(content) }
+
+    pub fn is_muted(&self, username: &str) -> bool {
+        let username = username.to_lowercase();
+        self.muted_users.iter().any(|muted| muted.to_lowercase() == username)
+    }
+
+    // strips control and zero-width characters from usernames and content, off passes them through untouched.
+    pub fn sanitize(&self) -> bool { self.sanitize }
+
+    // whitespace-only messages never make it through, whatever the configured minimum.
+    pub fn min_content_length(&self) -> usize { self.min_content_length.max(1) }
+
+    // at most `rate_limit` messages per user every `rate_limit_secs`, zero disables it.
+    pub fn rate_limit(&self) -> Option<(u32, Duration)> {
+        if self.rate_limit == 0 || self.rate_limit_secs == 0 { return None }
+        Some((self.rate_limit, Duration::from_secs(self.rate_limit_secs)))
+    }
+
+    pub fn dedup_window(&self) -> usize { self.dedup_window }
+
+    pub fn moderation_issuer(&self) -> bool { self.moderation_issuer }
+
+    // channels the game added later are governed by bridge_unknown_channels instead.
+    pub fn forwards(&self, channel: Channel) -> bool {
+        matches!(channel, Channel::Other(_)) || self.channels.contains(&channel)
+    }
+
+    pub fn bridge_unknown_channels(&self) -> bool { self.bridge_unknown_channels }
+
+    // the ids below the region channel, ChatChannel's System (0), Global (1) and Local (2). they are left out of the
+    // subscription unless asked for, and then bridged as Channel::Other by their id.
+    pub fn low_channels(&self) -> bool { self.low_channels }
+
+    // the kind of a raw channel id when channel_ids lists it, otherwise the game's own numbering applies.
+    pub fn channel(&self, id: i32) -> Option<Channel> {
+        self.channel_ids.iter().find(|entry| entry.id == id).map(|entry| entry.kind)
+    }
+
+    // by the raw id the message came in on, so two ids of one kind keep their own labels. messages from the bridge
+    // itself have no id and take the first label given to their kind.
+    pub fn channel_label(&self, channel: Channel, id: Option<i32>) -> Cow<'static, str> {
+        self.channel_ids
+            .iter()
+            .filter(|entry| !entry.label.is_empty())
+            .find(|entry| match id {
+                Some(id) => entry.id == id,
+                None => entry.kind == channel,
+            })
+            .map_or_else(|| channel.label(), |entry| entry.label.clone().into())
+    }
+
+    // anything older than this would rather flood the webhook than smooth over a restart.
+    pub fn backfill(&self) -> Duration { Duration::from_secs(self.backfill_seconds.min(MAX_BACKFILL_SECONDS)) }
+
+    pub fn subscriptions(&self) -> Option<&[String]> {
+        Some(self.subscriptions.as_slice()).filter(|queries| !queries.is_empty())
+    }
+
+    pub fn metrics_addr(&self) -> Option<&str> { non_empty(&self.metrics_addr) }
+
+    // how long without chat before a system message says so, zero never alerts. /readyz has its own limit.
+    pub fn stale_after(&self) -> Option<Duration> {
+        (self.stale_chat_minutes > 0).then(|| Duration::from_secs(self.stale_chat_minutes * 60))
+    }
+
+    // how long /readyz tolerates no chat at all before reporting not ready, zero never does.
+    pub fn ready_max_silence(&self) -> Option<Duration> {
+        (self.ready_max_silence_secs > 0).then(|| Duration::from_secs(self.ready_max_silence_secs))
+    }
+
+    pub fn show_timestamp(&self) -> bool { self.show_timestamp }
+
+    // discord renders <t:...> for every viewer, the file sink and the echo need the time spelled out.
+    pub fn format_time(&self, secs: i64) -> String {
+        let timezone = self.timezone.parse::<Tz>().unwrap_or(Tz::UTC);
+        let time = DateTime::from_timestamp(secs, 0).unwrap_or_default().with_timezone(&timezone);
+        match non_empty(&self.timestamp_format) {
+            Some(format) => time.format(format).to_string(),
+            None => time.to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+
+    pub fn announce_logins(&self) -> bool { self.announce_logins }
+
+    pub fn announce_logouts(&self) -> bool { self.announce_logouts }
+
+    pub fn announce_claims(&self) -> bool { self.announce_claims }
+
+    pub fn announce_connection(&self) -> bool { self.announce_connection }
+
+    pub fn message_capacity(&self) -> usize { self.message_capacity }
+
+    pub fn overflow(&self) -> Overflow { self.overflow }
+
+    // database updates waiting for the sieve. the sdk can not be made to wait, past this they are dropped whatever
+    // the overflow policy says.
+    pub fn update_capacity(&self) -> usize { self.update_capacity.max(1) }
+
+    pub fn api_addr(&self) -> &str { &self.api_addr }
+
+    pub fn api_buffer(&self) -> usize { self.api_buffer }
+
+    pub fn websocket_addr(&self) -> &str { &self.websocket_addr }
+
+    pub fn websocket_max_clients(&self) -> usize { self.websocket_max_clients }
+
+    pub fn dry_run(&self) -> bool { self.dry_run }
+
+    // every bridged message is also printed to stdout. with `sink = []` that is the only output.
+    pub fn echo(&self) -> bool { self.echo }
+
+    // failed attempts at the first connection before the bridge gives up, zero retries forever.
+    pub fn connect_attempts(&self) -> u32 { self.connect_attempts }
+
+    // a refused token stops the region instead of being retried forever, off treats it like any other error.
+    pub fn fatal_auth_errors(&self) -> bool { self.fatal_auth_errors }
+
+    // how long a webhook request may take to connect and to complete, zero waits forever.
+    pub fn timeouts(&self) -> (Duration, Duration) {
+        (Duration::from_secs(self.connect_timeout_secs), Duration::from_secs(self.request_timeout_secs))
+    }
+
+    // consecutive failures that open a sink's circuit, and how long it stays open. zero failures disables it.
+    pub fn breaker(&self) -> (u32, Duration) { (self.breaker_failures, Duration::from_secs(self.breaker_cooldown_secs)) }
+
+    // tells apart regions sharing a channel, e.g. "[EU] Alice", without touching the templates.
+    pub fn decorate(&self, username: String) -> String {
+        if self.username_prefix.is_empty() && self.username_suffix.is_empty() { return username }
+        format!("{}{}{}", self.username_prefix, username, self.username_suffix)
+    }
+
+    // tags region chat like claim and empire chat are tagged, empty leaves it untagged.
+    pub fn region_label(&self) -> Option<&str> { non_empty(&self.region_label) }
+
+    // the author as the templates lay it out, claim and empire chat carry their name as the tag, region chat its label.
+    // the claim and empire templates apply to the suffix position, prefix and none build on the chat template.
+    pub fn author(&self, channel: Channel, username: &str, tag: Option<&str>) -> String {
+        let chat = || render(&self.templates.chat, &[("username", username)]);
+        let Some(tag) = tag else { return chat() };
+        match (channel, self.tag_position) {
+            (Channel::Region, _) => render(&self.templates.region, &[("username", username), ("region", tag)]),
+            (_, TagPosition::None) => chat(),
+            (_, TagPosition::Prefix) => format!("[{}] {}", tag, chat()),
+            (Channel::Claim, TagPosition::Suffix) => render(&self.templates.claim, &[("username", username), ("claim", tag)]),
+            (_, TagPosition::Suffix) => render(&self.templates.empire, &[("username", username), ("empire", tag)]),
+        }
+    }
+
+    pub fn tag_position(&self) -> TagPosition { self.tag_position }
+
+    // where the newest announced moderation event is remembered across restarts.
+    pub fn moderation_state_path(&self) -> Option<&str> { non_empty(&self.moderation_state_path) }
+
+    // policies are named like the game names them (e.g. "BlockChat"), an empty list bridges every policy.
+    pub fn forwards_policy(&self, policy: &str) -> bool {
+        self.moderation_policies.is_empty() || self.moderation_policies.iter().any(|p| p.eq_ignore_ascii_case(policy))
+    }
+
+    pub fn log_dropped(&self) -> bool { self.log_dropped }
+
+    pub fn thread_id(&self, channel: Channel) -> Option<&str> { non_empty(self.threads.get(channel)) }
+
+    pub fn color(&self, channel: Channel) -> u32 { parse_color(self.colors.get(channel)).unwrap_or_default() }
+}
+
+enum FileFormat { Json, Toml }
+
+impl FileFormat {
+    fn of(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => FileFormat::Toml,
+            _ => FileFormat::Json,
+        }
+    }
+}
+
+fn is_discord_webhook(url: &Url) -> bool {
+    let host = url.host_str().unwrap_or_default();
+    let host = host.strip_prefix("ptb.").or(host.strip_prefix("canary.")).unwrap_or(host);
+    let path = url.path_segments().map(|s| s.filter(|s| !s.is_empty()).collect::<Vec<_>>()).unwrap_or_default();
+
+    matches!(host, "discord.com" | "discordapp.com")
+        && match path.as_slice() {
+            ["api", "webhooks", _, _] => true,
+            ["api", version, "webhooks", _, _] => version.starts_with('v'),
+            _ => false,
+        }
+}
+
+fn parse_color(color: &str) -> Option<u32> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() != 6 { return None }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+// in one pass over the template, so a value that itself reads like a placeholder is left as it is.
+fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let value = rest[start + 1..].split_once('}').and_then(|(name, _)| {
+            values.iter().find(|(other, _)| *other == name).map(|(_, value)| (name.len(), *value))
+        });
+        match value {
+            Some((len, value)) => { text.push_str(value); rest = &rest[start + len + 2..]; }
+            None => { text.push('{'); rest = &rest[start + 1..]; }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template.split('{').skip(1).filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+}
+
+fn non_empty(value: &str) -> Option<&str> { Some(value).filter(|value| !value.is_empty()) }
+
+fn is_slack_webhook(url: &Url) -> bool { url.host_str() == Some("hooks.slack.com") }
+
+fn env(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
 }
 
 pub trait Configurable<MOD>
@@ -47,8 +1161,20 @@ impl <MOD> Configurable<MOD> for DbConnectionBuilder<MOD>
 where MOD: SpacetimeModule
 {
     fn configure(self, config: &Config) -> Self {
-        self.with_uri(&config.cluster_url)
+        self.with_uri(config.cluster_uri())
             .with_module_name(&config.region)
-            .with_token(Some(&config.token))
+            .with_token(Some(config.token.expose()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_in_one_pass() {
+        let values = [("username", "{content}"), ("content", "{channel}"), ("channel", "Region")];
+        assert_eq!(render("{username}: {content} ({channel})", &values), "{content}: {channel} (Region)");
+        assert_eq!(render("{{username}} {unknown} {", &values), "{{content}} {unknown} {");
     }
 }
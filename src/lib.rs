@@ -0,0 +1,11 @@
+// the bridge as a library, for embedding it in another tokio application. the binary only parses flags and wires it up.
+pub mod glue;
+pub mod message;
+pub mod filter;
+pub mod metrics;
+pub mod sieve;
+pub mod outbox;
+pub mod webhook;
+pub mod queue;
+pub mod sink;
+pub mod bridge;
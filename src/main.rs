@@ -1,182 +1,124 @@
-use std::collections::HashMap;
-use bindings::region::{*, UserModerationPolicy::*};
-use bindings::ext::ctx::*;
-use bindings::sdk::{DbContext, Timestamp};
-
-mod glue;
-use glue::{Config, Configurable};
-
-use serde;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedSender, UnboundedReceiver};
-
-#[derive(serde::Serialize)]
-#[serde(untagged)]
-enum Message {
-    Disconnect,
-    Chat {
-        username: String,
-        content: String,
-    }
-}
-
-impl Message {
-    pub fn chat(username: String, content: String) -> Self { Self::Chat{ username, content } }
-
-    pub fn claim(username: String, claim: &str, content: String) -> Self {
-        Self::chat(format!("{} [{}]", username, claim), content)
-    }
-
-    pub fn empire(username: String, empire: &str, content: String) -> Self {
-        Self::chat(format!("{} [{}]", username, empire), content)
-    }
-
-    pub fn moderation(username: String, policy: &str, expiry: &str) -> Self {
-        Self::chat(
-            "<<MODERATION>>".to_string(),
-            format!("User {} has been banned from {} {}!", username, policy, expiry),
-        )
-    }
+use chatbridge::bridge::{region, run};
+use chatbridge::glue::{Config, Overrides};
+use chatbridge::metrics;
+use chatbridge::sieve::SharedNames;
+use tokio::sync::watch;
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+const CONFIG_PATHS: [&str; 2] = ["config.toml", "config.json"];
+
+#[derive(clap::Parser)]
+#[command(version, about)]
+struct Cli {
+    /// config file, defaults to config.toml or config.json in the working directory
+    #[arg(long)]
+    config: Option<String>,
+    /// print webhook payloads instead of posting them
+    #[arg(long)]
+    dry_run: bool,
+    /// log filter like "info" or "chatbridge=debug", takes precedence over RUST_LOG
+    #[arg(long)]
+    log_level: Option<String>,
+    /// webhook url to use instead of the configured ones
+    #[arg(long)]
+    webhook_url: Option<String>,
+    /// don't print bridged messages to stdout
+    #[arg(long, short)]
+    quiet: bool,
 }
 
 #[tokio::main]
 async fn main() {
-    let config = Config::from("config.json").expect("failed to load config.json");
+    let cli = <Cli as clap::Parser>::parse();
+    init_logging(cli.log_level.as_deref());
+
+    let path = cli.config.as_deref().unwrap_or_else(|| {
+        CONFIG_PATHS
+            .into_iter()
+            .find(|path| std::path::Path::new(path).exists())
+            .unwrap_or(CONFIG_PATHS[1])
+    });
+    let overrides = Overrides { webhook_url: cli.webhook_url.clone(), dry_run: cli.dry_run, quiet: cli.quiet };
+    let config = Config::from(path, &overrides).expect("failed to load config");
 
     if config.is_empty() {
-        eprintln!("please fill out the configuration file (config.json)!");
+        tracing::error!(path, "please fill out the configuration file!");
         return;
     }
 
-    let (tx_ctx, rx_ctx) = unbounded_channel::<DbUpdate>();
-    let (tx_msg, rx_msg) = unbounded_channel::<Message>();
-
-    let tx_shutdown = tx_msg.clone();
-    let ctx = DbConnection::builder()
-        .configure(&config)
-        .on_connect(|_, _, _| println!("connected!"))
-        .on_disconnect(move |_, _| {
-            println!("disconnected!");
-            tx_shutdown.send(Message::Disconnect).unwrap();
-        })
-        .with_channel(tx_ctx)
-        .build()
-        .expect("failed to connect");
-
-    let start = Timestamp::now();
-    ctx.subscription_builder()
-        .on_error(|_, err| eprintln!("subscription error: {}", err))
-        .subscribe([
-        "SELECT * FROM claim_state",
-        "SELECT * FROM empire_state",
-        "SELECT * FROM player_username_state",
-        &format!(r"SELECT t.*
-                   FROM chat_message_state t
-                   WHERE t.channel_id > 2
-                     AND t.timestamp > {}", start.to_micros_since_unix_epoch() / 1_000_000),
-        &format!(r"SELECT t.*
-                   FROM user_moderation_state t
-                   WHERE t.created_time > '{}'", start),
-    ]);
-
-    let (con, _, _) = tokio::join!(
-        tokio::spawn(ctx.run_until(tokio::signal::ctrl_c())),
-        tokio::spawn(sieve(rx_ctx, tx_msg)),
-        tokio::spawn(consume(rx_msg, config.webhook_url())),
-    );
-
-    if let Ok(Err(e)) = con { eprintln!("db error: {:?}", e); }
-}
-
-async fn sieve(mut rx: UnboundedReceiver<DbUpdate>, tx: UnboundedSender<Message>) {
-    const EMPIRE_INTERNAL: i32 = ChatChannel::EmpireInternal as i32;
-    const EMPIRE_PUBLIC: i32 = ChatChannel::EmpirePublic as i32;
-    const CLAIM: i32 = ChatChannel::Claim as i32;
-    const REGION: i32 = ChatChannel::Region as i32;
-
-
-    let mut claims = HashMap::new();
-    let mut empires = HashMap::new();
-    let mut players = HashMap::new();
+    if let Err(e) = config.validate() {
+        tracing::error!(error = %e, "invalid configuration");
+        return;
+    }
 
+    let (_watcher, rx_config) = config.watch(path, overrides).expect("failed to watch config");
 
-    while let Some(update) = rx.recv().await {
-        for claim in update.claim_state.inserts {
-            claims.insert(claim.row.entity_id, claim.row.name);
-        }
-        for empire in update.empire_state.inserts {
-            empires.insert(empire.row.entity_id, empire.row.name);
-        }
-        for player in update.player_username_state.inserts {
-            players.insert(player.row.entity_id, player.row.username);
+    {
+        let config = rx_config.borrow();
+        if let Some(addr) = config.metrics_addr() {
+            tokio::spawn(metrics::serve(addr.to_string(), config.ready_max_silence()));
         }
+    }
 
-        for msg in update.chat_message_state.inserts {
-            let msg = match msg.row.channel_id {
-                EMPIRE_INTERNAL | EMPIRE_PUBLIC =>
-                    empires
-                        .get(&msg.row.target_id)
-                        .map(|e| Message::empire(msg.row.username, e, msg.row.text)),
-                CLAIM =>
-                    claims
-                        .get(&msg.row.target_id)
-                        .map(|e| Message::claim(msg.row.username, e, msg.row.text)),
-                REGION =>
-                    Some(Message::chat(msg.row.username, msg.row.text)),
-                _ => None,
-            };
-
-            if let Some(msg) = msg { tx.send(msg).unwrap() }
-        }
+    let (tx_stop, rx_stop) = watch::channel(false);
+    tokio::spawn(async move {
+        terminated().await;
+        tracing::info!("shutting down...");
+        let _ = tx_stop.send(true);
+    });
+
+    let regions = rx_config.borrow().regions().len();
+    let pipelines = (0..regions)
+        .map(|index| {
+            let rx_region = region(rx_config.clone(), index);
+            let span = tracing::info_span!("region", name = rx_region.borrow().region());
+            // entity ids are per region, so is the cache resolving them.
+            tokio::spawn(run(rx_region, rx_stop.clone(), SharedNames::default()).instrument(span))
+        })
+        .collect::<Vec<_>>();
 
-        for msg in update.user_moderation_state.inserts {
-            let user = players
-                .get(&msg.row.target_entity_id)
-                .map_or(format!("{{{}}}", msg.row.target_entity_id), &String::to_string);
-
-            let msg = match msg.row.user_moderation_policy {
-                PermanentBlockLogin =>
-                    Message::moderation(user, "logging in", "permanently"),
-                TemporaryBlockLogin =>
-                    Message::moderation(user, "logging in", &as_expiry(msg.row.expiration_time)),
-                BlockChat =>
-                    Message::moderation(user, "chatting", &as_expiry(msg.row.expiration_time)),
-                BlockConstruct =>
-                    Message::moderation(user, "building", &as_expiry(msg.row.expiration_time)),
-            };
-
-            tx.send(msg).unwrap();
+    let mut failed = false;
+    for pipeline in pipelines {
+        if let Ok(Err(e)) = pipeline.await {
+            tracing::error!(error = %e, "bridge stopped");
+            failed = true;
         }
     }
+    // a supervisor should see that the bridge gave up rather than shut down.
+    if failed { std::process::exit(1); }
 }
 
-fn as_expiry(expiry: Timestamp) -> String {
-    format!("until <t:{}:f>!", expiry.to_micros_since_unix_epoch() / 1_000_000)
+// --log-level or RUST_LOG picks the level (info by default), CHATBRIDGE_LOG_FORMAT=json switches to one json object per line.
+fn init_logging(level: Option<&str>) {
+    let filter = match level {
+        Some(level) => EnvFilter::new(level),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let logs = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr);
+
+    match std::env::var("CHATBRIDGE_LOG_FORMAT").as_deref() {
+        Ok("json") => logs.json().init(),
+        _ => logs.init(),
+    }
 }
 
-async fn consume(mut rx: UnboundedReceiver<Message>, webhook_url: String) {
-    let client = reqwest::Client::new();
-
-    while let Some(msg) = rx.recv().await {
-        match &msg {
-            Message::Disconnect => { break }
-            Message::Chat { username, content } => {
-                println!("{}: {}", username, content);
-                if webhook_url.is_empty() {
-                    continue;
-                }
-
-                let payload = serde_json::to_string(&msg).unwrap();
-                let response = client
-                    .post(&webhook_url)
-                    .header("Content-Type", "application/json")
-                    .body(payload)
-                    .send()
-                    .await;
-
-                if !response.is_ok_and(|r| r.status().is_success()) {
-                    eprintln!("failed to send message");
-                }
-            }
-        }
+// orchestrators stop containers with SIGTERM, a terminal sends SIGINT.
+#[cfg(unix)]
+async fn terminated() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let Ok(mut sigterm) = signal(SignalKind::terminate()) else {
+        let _ = tokio::signal::ctrl_c().await;
+        return
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = sigterm.recv() => {},
     }
-}
\ No newline at end of file
+}
+
+#[cfg(not(unix))]
+async fn terminated() {
+    let _ = tokio::signal::ctrl_c().await;
+}
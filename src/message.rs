@@ -0,0 +1,216 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Region,
+    Claim,
+    EmpirePublic,
+    EmpireInternal,
+    Moderation,
+    // a chat channel the game added after this was written, by its raw id.
+    Other(i32),
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] = [
+        Channel::Region, Channel::Claim, Channel::EmpirePublic, Channel::EmpireInternal, Channel::Moderation,
+    ];
+
+    pub fn label(&self) -> Cow<'static, str> {
+        match self {
+            Channel::Region         => "Region".into(),
+            Channel::Claim          => "Claim".into(),
+            Channel::EmpirePublic   => "Empire Public".into(),
+            Channel::EmpireInternal => "Empire Internal".into(),
+            Channel::Moderation     => "Moderation".into(),
+            Channel::Other(id)      => format!("Channel {}", id).into(),
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(untagged)]
+pub enum Message {
+    Disconnect,
+    Chat {
+        username: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tag: Option<String>,
+        content: String,
+        channel: Channel,
+        // the raw id the game sent it on, which channel_ids can label apart from other ids of the same kind.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel_id: Option<i32>,
+        // the raw claim or empire id the tag was resolved from, the tag itself may be a placeholder.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        target_id: Option<u64>,
+        // unix seconds of when it was said in game.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp: Option<i64>,
+        // counts up with every message of the region, so consumers can order them and notice gaps.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    Moderation {
+        user:   String,
+        policy: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        expiry: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        issuer: Option<String>,
+        kind:   ModerationKind,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seq:    Option<u64>,
+    },
+    // announcements from the bridge itself, about the region or the bridge rather than anything said in game.
+    System {
+        content: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp: Option<i64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+}
+
+// the one counter behind `seq`, shared by the sieve and the bridge's own announcements.
+#[derive(Clone, Default)]
+pub struct Sequence(Arc<AtomicU64>);
+
+impl Sequence {
+    pub fn number(&self, msg: Message) -> Message { msg.sequenced(self.0.fetch_add(1, Ordering::Relaxed) + 1) }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationKind {
+    Ban,
+    Unban,
+    // a policy the bridge has no wording for, `policy` holds its raw name.
+    Other,
+}
+
+impl Message {
+    fn said(channel: Channel, username: String, tag: Option<&str>, content: String) -> Self {
+        let tag = tag.map(str::to_string);
+        Self::Chat{ username, tag, content, channel, channel_id: None, target_id: None, timestamp: None, seq: None }
+    }
+
+    pub fn chat(channel: Channel, username: String, content: String) -> Self { Self::said(channel, username, None, content) }
+
+    pub fn claim(username: String, claim: &str, content: String) -> Self {
+        Self::said(Channel::Claim, username, Some(claim), content)
+    }
+
+    pub fn region(username: String, label: &str, content: String) -> Self {
+        Self::said(Channel::Region, username, Some(label), content)
+    }
+
+    pub fn empire(channel: Channel, username: String, empire: &str, content: String) -> Self {
+        Self::said(channel, username, Some(empire), content)
+    }
+
+    pub fn on(mut self, id: i32) -> Self {
+        if let Self::Chat { channel_id, .. } = &mut self { *channel_id = Some(id); }
+        self
+    }
+
+    pub fn target(mut self, id: u64) -> Self {
+        if let Self::Chat { target_id, .. } = &mut self { *target_id = Some(id); }
+        self
+    }
+
+    pub fn moderation(user: String, policy: &str, expiry: &str, issuer: Option<&str>) -> Self {
+        Self::Moderation {
+            user,
+            policy: policy.to_string(),
+            expiry: Some(expiry.to_string()),
+            issuer: issuer.map(str::to_string),
+            kind: ModerationKind::Ban,
+            seq: None,
+        }
+    }
+
+    pub fn unban(user: String, policy: &str) -> Self {
+        Self::Moderation { user, policy: policy.to_string(), expiry: None, issuer: None, kind: ModerationKind::Unban, seq: None }
+    }
+
+    pub fn at(mut self, secs: i64) -> Self {
+        if let Self::Chat { timestamp, .. } | Self::System { timestamp, .. } = &mut self { *timestamp = Some(secs); }
+        self
+    }
+
+    pub fn sequenced(mut self, n: u64) -> Self {
+        if let Self::Chat { seq, .. } | Self::Moderation { seq, .. } | Self::System { seq, .. } = &mut self { *seq = Some(n); }
+        self
+    }
+
+    pub fn system(content: String) -> Self { Self::System { content, timestamp: None, seq: None } }
+
+    pub fn moderated(user: String, policy: &str) -> Self {
+        Self::Moderation { user, policy: policy.to_string(), expiry: None, issuer: None, kind: ModerationKind::Other, seq: None }
+    }
+
+    // a summary of several moderation events, already rendered into its text.
+    pub fn notice(content: String) -> Self { Self::chat(Channel::Moderation, "<<MODERATION>>".to_string(), content) }
+
+    pub fn is_moderation(&self) -> bool { self.channel() == Some(Channel::Moderation) }
+
+    pub fn tag(&self) -> Option<&str> {
+        match self {
+            Self::Chat { tag, .. } => tag.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn channel(&self) -> Option<Channel> {
+        match self {
+            Self::Disconnect => None,
+            Self::Chat { channel, .. } => Some(*channel),
+            Self::Moderation { .. } => Some(Channel::Moderation),
+            Self::System { .. } => Some(Channel::Region),
+        }
+    }
+
+    // sinks without a notion of moderation or system events show them as a chat line from a marker username.
+    pub fn plain(self) -> Self {
+        if let Self::System { content, timestamp, seq } = self {
+            let (username, channel) = ("<<SYSTEM>>".to_string(), Channel::Region);
+            return Self::Chat { username, tag: None, content, channel, channel_id: None, target_id: None, timestamp, seq }
+        }
+        let Self::Moderation { user, policy, expiry, issuer, kind, seq } = self else { return self };
+
+        let content = match kind {
+            ModerationKind::Ban => {
+                let issuer = issuer.map(|issuer| format!(" (by {})", issuer)).unwrap_or_default();
+                format!("User {} has been banned from {} {}{}!", user, policy, expiry.unwrap_or_default(), issuer)
+            }
+            ModerationKind::Unban => format!("User {}'s ban from {} has been lifted!", user, policy),
+            ModerationKind::Other => format!("User {} has been moderated (policy: {})!", user, policy),
+        };
+        match seq {
+            Some(n) => Self::notice(content).sequenced(n),
+            None => Self::notice(content),
+        }
+    }
+
+    // stands in for the first message a user sends over the rate limit, nothing of what they wrote is kept.
+    pub fn throttled(self) -> Self {
+        match self {
+            Self::Chat { username, tag, channel, channel_id, target_id, timestamp, seq, .. } => Self::Chat {
+                username: "<<RATE LIMIT>>".to_string(),
+                tag,
+                content: format!("User {} is being rate-limited!", username),
+                channel,
+                channel_id,
+                target_id,
+                timestamp,
+                seq,
+            },
+            msg => msg,
+        }
+    }
+}
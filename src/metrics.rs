@@ -0,0 +1,117 @@
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use prometheus::{Encoder, IntCounter, IntCounterVec, IntGaugeVec, TextEncoder};
+use prometheus::{register_int_counter, register_int_counter_vec, register_int_gauge_vec};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use crate::message::Channel;
+
+static BRIDGED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("chatbridge_messages_bridged_total", "messages handed to the sinks", &["channel"]).unwrap()
+});
+static DROPPED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("chatbridge_messages_dropped_total", "chat messages filtered out", &["reason"]).unwrap()
+});
+static DELIVERIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("chatbridge_deliveries_total", "batches delivered by each sink", &["sink", "result"]).unwrap()
+});
+static RETRIES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("chatbridge_webhook_retries_total", "webhook requests sent again after a failure").unwrap()
+});
+static RECONNECTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("chatbridge_reconnects_total", "reconnects to spacetimedb").unwrap()
+});
+static CONNECTED: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("chatbridge_connected", "whether the bridge is connected to spacetimedb", &["region"]).unwrap()
+});
+static SUBSCRIBED: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("chatbridge_subscribed", "whether the subscription is applied", &["region"]).unwrap()
+});
+static LAST_CHAT: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "chatbridge_last_chat_timestamp_seconds", "when the last chat message came in, in unix seconds", &["region"]
+    ).unwrap()
+});
+// every region this process bridges, including those that never connected.
+static REGIONS: LazyLock<Mutex<Vec<String>>> = LazyLock::new(Mutex::default);
+
+pub fn bridged(channel: Channel) { BRIDGED.with_label_values(&[&channel.label()]).inc(); }
+
+pub fn dropped(reason: &str) { DROPPED.with_label_values(&[reason]).inc(); }
+
+pub fn dropped_many(reason: &str, count: u64) { DROPPED.with_label_values(&[reason]).inc_by(count); }
+
+pub fn delivered(sink: &str, ok: bool) {
+    DELIVERIES.with_label_values(&[sink, if ok { "success" } else { "failure" }]).inc();
+}
+
+pub fn retried() { RETRIES.inc(); }
+
+pub fn reconnecting() { RECONNECTS.inc(); }
+
+// registers a region before its first connection, so one that never connects still holds up /readyz.
+pub fn region(region: &str) {
+    let mut regions = REGIONS.lock().unwrap();
+    if regions.iter().any(|other| other == region) { return }
+    regions.push(region.to_string());
+    connected(region, false);
+    subscribed(region, false);
+}
+
+pub fn connected(region: &str, connected: bool) { CONNECTED.with_label_values(&[region]).set(connected as i64); }
+
+pub fn subscribed(region: &str, subscribed: bool) { SUBSCRIBED.with_label_values(&[region]).set(subscribed as i64); }
+
+pub fn chatted(region: &str) { LAST_CHAT.with_label_values(&[region]).set(chrono::Utc::now().timestamp()); }
+
+// every region connected and subscribed, and unless `max_silence` is off, chat came in recently enough to trust
+// each feed.
+fn is_ready(max_silence: Option<Duration>) -> bool {
+    let now = chrono::Utc::now().timestamp();
+    let regions = REGIONS.lock().unwrap();
+    !regions.is_empty() && regions.iter().all(|region| {
+        let silence = now - LAST_CHAT.with_label_values(&[region]).get();
+        CONNECTED.with_label_values(&[region]).get() == 1
+            && SUBSCRIBED.with_label_values(&[region]).get() == 1
+            && max_silence.is_none_or(|max| silence <= max.as_secs() as i64)
+    })
+}
+
+// just enough http for a scraper and a prober: the request line is all that is looked at, every response closes
+// the connection. /healthz only says the process is up, /readyz whether it is bridging.
+pub async fn serve(addr: String, max_silence: Option<Duration>) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => { tracing::error!(addr, error = %e, "failed to bind metrics server"); return }
+    };
+    tracing::info!(addr, "serving metrics");
+
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else { continue };
+        tokio::spawn(async move {
+            let mut request = [0u8; 1024];
+            let Ok(read) = stream.read(&mut request).await else { return };
+            let request = String::from_utf8_lossy(&request[..read]);
+
+            let (status, body) = match request.split_whitespace().nth(1).unwrap_or("/") {
+                "/metrics" => ("200 OK", render()),
+                "/healthz" => ("200 OK", "ok\n".to_string()),
+                "/readyz" if is_ready(max_silence) => ("200 OK", "ready\n".to_string()),
+                "/readyz" => ("503 Service Unavailable", "not ready\n".to_string()),
+                _ => ("404 Not Found", "not found\n".to_string()),
+            };
+
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status, body.len(), body,
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+fn render() -> String {
+    let mut buffer = Vec::new();
+    let _ = TextEncoder::new().encode(&prometheus::gather(), &mut buffer);
+    String::from_utf8(buffer).unwrap_or_default()
+}
@@ -0,0 +1,92 @@
+use tokio::sync::{broadcast, mpsc};
+use crate::glue::Overflow;
+use crate::message::Message;
+use crate::metrics;
+
+// the bounded channel between sieve and consume, either making the sieve wait or overwriting the oldest message.
+pub enum Sender {
+    Block(mpsc::Sender<Message>),
+    DropOldest(broadcast::Sender<Message>),
+}
+
+pub enum Receiver {
+    Block(mpsc::Receiver<Message>),
+    DropOldest(broadcast::Receiver<Message>),
+}
+
+#[derive(Debug)]
+pub struct Closed;
+
+pub fn bounded(capacity: usize, overflow: Overflow) -> (Sender, Receiver) {
+    let capacity = capacity.max(1);
+    match overflow {
+        Overflow::Block => {
+            let (tx, rx) = mpsc::channel(capacity);
+            (Sender::Block(tx), Receiver::Block(rx))
+        }
+        Overflow::DropOldest => {
+            let (tx, rx) = broadcast::channel(capacity);
+            (Sender::DropOldest(tx), Receiver::DropOldest(rx))
+        }
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Block(tx) => Self::Block(tx.clone()),
+            Self::DropOldest(tx) => Self::DropOldest(tx.clone()),
+        }
+    }
+}
+
+impl Sender {
+    pub async fn send(&self, msg: Message) -> Result<(), Closed> {
+        match self {
+            Self::Block(tx) => tx.send(msg).await.map_err(|_| Closed),
+            Self::DropOldest(tx) => tx.send(msg).map(|_| ()).map_err(|_| Closed),
+        }
+    }
+
+    // for callbacks that can not wait, a full buffer drops the message instead.
+    pub fn try_send(&self, msg: Message) -> Result<(), Closed> {
+        match self {
+            Self::Block(tx) => tx.try_send(msg).map_err(|_| Closed),
+            Self::DropOldest(tx) => tx.send(msg).map(|_| ()).map_err(|_| Closed),
+        }
+    }
+}
+
+impl Receiver {
+    pub async fn recv(&mut self) -> Option<Message> {
+        match self {
+            Self::Block(rx) => rx.recv().await,
+            Self::DropOldest(rx) => loop {
+                match rx.recv().await {
+                    Ok(msg) => return Some(msg),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => lagged(skipped),
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            },
+        }
+    }
+
+    // whatever is still buffered, without waiting for more.
+    pub fn try_recv(&mut self) -> Option<Message> {
+        match self {
+            Self::Block(rx) => rx.try_recv().ok(),
+            Self::DropOldest(rx) => loop {
+                match rx.try_recv() {
+                    Ok(msg) => return Some(msg),
+                    Err(broadcast::error::TryRecvError::Lagged(skipped)) => lagged(skipped),
+                    Err(_) => return None,
+                }
+            },
+        }
+    }
+}
+
+fn lagged(skipped: u64) {
+    tracing::warn!(dropped = skipped, "message buffer full, dropped oldest messages");
+    metrics::dropped_many("overflow", skipped);
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Entry {
+    pub url:     String,
+    pub payload: String,
+}
+
+// append-only json-lines file mirrored in memory, the file is only rewritten when entries leave the queue.
+pub struct Queue {
+    path:     PathBuf,
+    capacity: usize,
+    entries:  VecDeque<Entry>,
+}
+
+impl Queue {
+    pub fn open(path: &str, capacity: usize) -> Result<Self> {
+        let path = PathBuf::from(path);
+        let mut entries = VecDeque::new();
+
+        if path.exists() {
+            for line in std::fs::read_to_string(&path)?.lines().filter(|line| !line.trim().is_empty()) {
+                match serde_json::from_str(line) {
+                    Ok(entry) => entries.push_back(entry),
+                    Err(e) => tracing::warn!(error = %e, "skipping corrupt queue entry"),
+                }
+            }
+        }
+
+        let mut queue = Self { path, capacity: capacity.max(1), entries };
+        if queue.trim() { queue.persist()?; }
+        Ok(queue)
+    }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    pub fn front(&self) -> Option<&Entry> { self.entries.front() }
+
+    pub fn pop_front(&mut self) { self.entries.pop_front(); }
+
+    pub fn push(&mut self, entry: Entry) -> Result<()> {
+        let line = serde_json::to_string(&entry)?;
+        self.entries.push_back(entry);
+        if self.trim() { return self.persist() }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+
+    pub fn persist(&self) -> Result<()> {
+        let mut content = String::new();
+        for entry in &self.entries {
+            content.push_str(&serde_json::to_string(entry)?);
+            content.push('\n');
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn trim(&mut self) -> bool {
+        let excess = self.entries.len().saturating_sub(self.capacity);
+        if excess > 0 {
+            tracing::warn!(dropped = excess, "queue full, dropping oldest messages");
+            self.entries.drain(..excess);
+        }
+        excess > 0
+    }
+}
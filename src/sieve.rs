@@ -0,0 +1,517 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use bindings::region::{*, UserModerationPolicy::*};
+use bindings::ext::ctx::*;
+use bindings::sdk::Timestamp;
+use tokio::sync::mpsc::{Receiver, UnboundedReceiver};
+use tokio::sync::watch;
+use crate::filter::{sanitize, Admit, Recent, Throttle, Watermark};
+use crate::glue::Config;
+use crate::message::{Channel, Message, ModerationKind, Sequence};
+use crate::{metrics, outbox};
+
+const EMPIRE_INTERNAL: i32 = ChatChannel::EmpireInternal as i32;
+const EMPIRE_PUBLIC: i32 = ChatChannel::EmpirePublic as i32;
+const CLAIM: i32 = ChatChannel::Claim as i32;
+const REGION: i32 = ChatChannel::Region as i32;
+
+// names already present when the subscription is applied, so early messages can be resolved.
+pub struct Snapshot {
+    pub claims:  Vec<(u64, String)>,
+    pub empires: Vec<(u64, String)>,
+    pub players: Vec<(u64, String)>,
+}
+
+// entity names by id, shared with every task that has to resolve them.
+#[derive(Default)]
+pub struct Names {
+    claims:  HashMap<u64, String>,
+    empires: HashMap<u64, String>,
+    players: HashMap<u64, String>,
+}
+
+impl Names {
+    pub fn claim(&self, id: u64) -> Option<&str> { self.claims.get(&id).map(String::as_str) }
+
+    pub fn empire(&self, id: u64) -> Option<&str> { self.empires.get(&id).map(String::as_str) }
+
+    pub fn player(&self, id: u64) -> Option<&str> { self.players.get(&id).map(String::as_str) }
+}
+
+// the sieve is the only writer and locks once per update, readers should copy out what they need and never hold
+// the lock across an await, so neither side waits on the other for long.
+pub type SharedNames = Arc<RwLock<Names>>;
+
+// everything the sieve remembers between updates.
+#[derive(Default)]
+pub struct Caches {
+    names:    SharedNames,
+    blocked:  u64,
+    throttle: Throttle,
+    recent:   Recent,
+    moderation: Watermark,
+    // moderation of players whose name is not known yet, until they resolve or their time is up.
+    pending:  Vec<(Instant, UserModerationState)>,
+    unknown_channels: HashSet<i32>,
+    seq:      Sequence,
+}
+
+impl Caches {
+    pub fn shared(names: SharedNames) -> Self { Self { names, ..Self::default() } }
+
+    pub fn extend(&mut self, snapshot: Snapshot) {
+        let mut names = self.names.write().unwrap();
+        names.claims.extend(snapshot.claims);
+        names.empires.extend(snapshot.empires);
+        names.players.extend(snapshot.players);
+    }
+}
+
+pub async fn sieve(
+    mut rx: Receiver<DbUpdate>,
+    mut rx_snapshot: UnboundedReceiver<Snapshot>,
+    tx: outbox::Sender,
+    rx_config: watch::Receiver<Config>,
+    names: SharedNames,
+    seq: Sequence,
+) {
+    let mut caches = Caches { seq, ..Caches::shared(names) };
+    caches.moderation = Watermark::load(rx_config.borrow().moderation_state_path());
+
+    loop {
+        let update = tokio::select! {
+            biased;
+            Some(snapshot) = rx_snapshot.recv() => {
+                caches.extend(snapshot);
+                continue
+            }
+            update = rx.recv() => update,
+        };
+        let Some(update) = update else { break };
+
+        let messages = process_update(&mut caches, update, &rx_config.borrow());
+        for msg in messages {
+            if tx.send(msg).await.is_err() {
+                tracing::warn!("message channel closed, stopping sieve");
+                return;
+            }
+        }
+    }
+}
+
+// applies one database update to the caches and returns the messages it produced, in order.
+pub fn process_update(caches: &mut Caches, update: DbUpdate, config: &Config) -> Vec<Message> {
+    let mut messages = Vec::new();
+    let mut names = caches.names.write().unwrap();
+
+    for claim in update.claim_state.deletes {
+        names.claims.remove(&claim.row.entity_id);
+    }
+    for claim in update.claim_state.updates {
+        if config.announce_claims() && claim.old.name != claim.new.name {
+            messages.push(Message::system(format!("Claim '{}' renamed to '{}'", claim.old.name, claim.new.name)));
+        }
+        names.claims.insert(claim.new.entity_id, claim.new.name);
+    }
+    for claim in update.claim_state.inserts {
+        // claims already known from the snapshot are not new, only new to this connection.
+        let known = names.claims.contains_key(&claim.row.entity_id);
+        if config.announce_claims() && !known {
+            messages.push(Message::system(format!("New claim '{}' founded", claim.row.name)));
+        }
+        names.claims.insert(claim.row.entity_id, claim.row.name);
+    }
+
+    for empire in update.empire_state.deletes {
+        names.empires.remove(&empire.row.entity_id);
+    }
+    for empire in update.empire_state.updates {
+        names.empires.insert(empire.new.entity_id, empire.new.name);
+    }
+    for empire in update.empire_state.inserts {
+        names.empires.insert(empire.row.entity_id, empire.row.name);
+    }
+
+    for player in update.player_username_state.deletes {
+        names.players.remove(&player.row.entity_id);
+    }
+    for player in update.player_username_state.updates {
+        names.players.insert(player.new.entity_id, player.new.username);
+    }
+    for player in update.player_username_state.inserts {
+        names.players.insert(player.row.entity_id, player.row.username);
+    }
+
+    for session in update.signed_in_player_state.inserts {
+        if !config.announce_logins() { break }
+        let user = player(&names, session.row.entity_id, config);
+        messages.push(Message::system(format!("Player {} logged in", user)));
+    }
+    for session in update.signed_in_player_state.deletes {
+        if !config.announce_logouts() { break }
+        let user = player(&names, session.row.entity_id, config);
+        messages.push(Message::system(format!("Player {} logged out", user)));
+    }
+
+    let chats = update.chat_message_state.inserts;
+    if !chats.is_empty() { metrics::chatted(config.region()); }
+    for mut msg in chronological(chats, |msg| msg.row.timestamp) {
+        let channel = match as_channel(msg.row.channel_id, config) {
+            Some(channel) => channel,
+            None => {
+                let id = msg.row.channel_id;
+                // once per id is enough to notice the game added a channel.
+                if caches.unknown_channels.insert(id) { tracing::warn!(channel_id = id, "chat message on unknown channel"); }
+                if !config.bridge_unknown_channels() { continue }
+                Channel::Other(id)
+            }
+        };
+        if !config.forwards(channel) { metrics::dropped("channel"); continue }
+
+        let row = (msg.row.channel_id, msg.row.target_id, &msg.row.username, &msg.row.text, msg.row.timestamp);
+        if caches.recent.is_repeat(&row, config.dedup_window()) { metrics::dropped("duplicate"); continue }
+
+        // before the filters look at it, so invisible characters can not split a muted name or a blocked word.
+        if config.sanitize() {
+            msg.row.username = sanitize(&msg.row.username);
+            msg.row.text = sanitize(&msg.row.text);
+        }
+        let Some(username) = named(std::mem::take(&mut msg.row.username), config) else {
+            metrics::dropped("empty_username");
+            continue
+        };
+        msg.row.username = username;
+        if config.is_muted(&msg.row.username) { metrics::dropped("muted"); continue }
+        if msg.row.text.trim().chars().count() < config.min_content_length() { metrics::dropped("short"); continue }
+
+        if config.is_blocked(&msg.row.text) {
+            caches.blocked += 1;
+            metrics::dropped("blocklist");
+            if config.log_dropped() {
+                tracing::info!(username = %msg.row.username, dropped = caches.blocked, "dropped message matching the blocklist");
+            }
+            continue
+        }
+
+        msg.row.text = config.mask(&msg.row.text);
+
+        let admit = config.rate_limit()
+            .map_or(Admit::Pass, |(messages, per)| caches.throttle.admit(&msg.row.username, messages, per));
+        if admit == Admit::Drop { metrics::dropped("rate_limit"); continue }
+
+        let (timestamp, channel_id) = (msg.row.timestamp as i64, msg.row.channel_id);
+        msg.row.username = config.decorate(msg.row.username);
+        let msg = match channel {
+            Channel::EmpireInternal | Channel::EmpirePublic => {
+                let empire = resolve(names.empire(msg.row.target_id), msg.row.target_id, msg.row.channel_id, config);
+                Message::empire(channel, msg.row.username, &empire, msg.row.text).target(msg.row.target_id)
+            }
+            Channel::Claim => {
+                let claim = resolve(names.claim(msg.row.target_id), msg.row.target_id, msg.row.channel_id, config);
+                Message::claim(msg.row.username, &claim, msg.row.text).target(msg.row.target_id)
+            }
+            Channel::Region => match config.region_label() {
+                Some(label) => Message::region(msg.row.username, label, msg.row.text),
+                None => Message::chat(channel, msg.row.username, msg.row.text),
+            },
+            _ => Message::chat(channel, msg.row.username, msg.row.text),
+        };
+        let msg = msg.on(channel_id).at(timestamp);
+        let msg = if admit == Admit::Notify { msg.throttled() } else { msg };
+        metrics::bridged(channel);
+        messages.push(msg);
+    }
+
+    // checked on every update, so a held back event goes out with the first update after its name arrived.
+    let now = Instant::now();
+    let (ready, waiting) = std::mem::take(&mut caches.pending)
+        .into_iter()
+        .partition::<Vec<_>, _>(|(until, row)| now >= *until || names.player(row.target_entity_id).is_some());
+    caches.pending = waiting;
+    let mut bans = Vec::new();
+    for (_, row) in ready {
+        bans.push(moderation(&names, &row, config));
+    }
+
+    // oldest first, an older event behind a newer one would fall below the watermark.
+    let inserts = chronological(update.user_moderation_state.inserts, |msg| msg.row.created_time.to_micros_since_unix_epoch());
+    for msg in inserts {
+        if !config.forwards_policy(&format!("{:?}", msg.row.user_moderation_policy)) { metrics::dropped("policy"); continue }
+        let created = msg.row.created_time.to_micros_since_unix_epoch();
+        if caches.moderation.is_repeat(msg.row.entity_id, created) { metrics::dropped("duplicate"); continue }
+
+        if let Some(wait) = config.moderation_resolve_wait() && names.player(msg.row.target_entity_id).is_none() {
+            caches.pending.push((now + wait, msg.row));
+            continue
+        }
+        bans.push(moderation(&names, &msg.row, config));
+    }
+    match config.moderation_collapse() {
+        Some(limit) => messages.extend(collapse(bans, limit)),
+        None => messages.extend(bans),
+    }
+
+    for msg in update.user_moderation_state.deletes {
+        if !config.forwards_policy(&format!("{:?}", msg.row.user_moderation_policy)) { metrics::dropped("policy"); continue }
+        let user = player(&names, msg.row.target_entity_id, config);
+        metrics::bridged(Channel::Moderation);
+        let policy = as_policy(&msg.row.user_moderation_policy)
+            .map_or_else(|| format!("{:?}", msg.row.user_moderation_policy), str::to_string);
+        messages.push(Message::unban(user, &policy));
+    }
+
+    // numbered only once it is certain they go out, a dropped message leaves no gap.
+    messages.into_iter().map(|msg| caches.seq.number(msg)).collect()
+}
+
+fn moderation(names: &Names, row: &UserModerationState, config: &Config) -> Message {
+    let user = player(names, row.target_entity_id, config);
+    metrics::bridged(Channel::Moderation);
+
+    let Some(policy) = as_policy(&row.user_moderation_policy) else {
+        tracing::warn!(policy = ?row.user_moderation_policy, "unknown moderation policy");
+        return Message::moderated(user, &format!("{:?}", row.user_moderation_policy))
+    };
+    let issuer = config.moderation_issuer().then(|| player(names, row.created_by_entity_id, config));
+
+    let expiry = as_expiry(&row.user_moderation_policy, row.expiration_time);
+    Message::moderation(user, policy, &expiry, issuer.as_deref())
+}
+
+// a ban wave turns into one notice per policy, with a detail line for only the first `limit` players.
+fn collapse(bans: Vec<Message>, limit: usize) -> Vec<Message> {
+    let mut messages = Vec::new();
+    let mut waves: Vec<(String, Vec<Message>)> = Vec::new();
+    for msg in bans {
+        match &msg {
+            Message::Moderation { policy, kind: ModerationKind::Ban, .. } => {
+                match waves.iter_mut().find(|(other, _)| other == policy) {
+                    Some((_, wave)) => wave.push(msg),
+                    None => waves.push((policy.clone(), vec![msg])),
+                }
+            }
+            _ => messages.push(msg),
+        }
+    }
+
+    for (policy, wave) in waves {
+        if wave.len() <= limit {
+            messages.extend(wave);
+            continue
+        }
+
+        let mut lines = vec![format!("{} players have been banned from {}!", wave.len(), policy)];
+        let more = wave.len() - limit;
+        for msg in wave.into_iter().take(limit) {
+            if let Message::Chat { content, .. } = msg.plain() { lines.push(format!("- {}", content)); }
+        }
+        lines.push(format!("...and {} more.", more));
+        messages.push(Message::notice(lines.join("\n")));
+    }
+    messages
+}
+
+fn player(names: &Names, id: u64, config: &Config) -> String {
+    names.player(id).map_or_else(|| {
+        tracing::debug!(entity_id = id, "unresolved player");
+        config.unresolved_player(id)
+    }, str::to_string)
+}
+
+// the sdk hands inserts over in no particular order, a stable sort keeps messages of the same second as they came.
+fn chronological<T, K: Ord>(mut rows: Vec<T>, timestamp: impl FnMut(&T) -> K) -> Vec<T> {
+    rows.sort_by_key(timestamp);
+    rows
+}
+
+// chat the game sends without a player, e.g. from npcs, goes out under empty_username or not at all.
+fn named(username: String, config: &Config) -> Option<String> {
+    if !username.trim().is_empty() { return Some(username) }
+    config.empty_username().map(str::to_string)
+}
+
+// channel_ids first, then the game's own numbering. None is a channel the game added after this was written.
+fn as_channel(id: i32, config: &Config) -> Option<Channel> {
+    config.channel(id).or(match id {
+        EMPIRE_INTERNAL => Some(Channel::EmpireInternal),
+        EMPIRE_PUBLIC => Some(Channel::EmpirePublic),
+        CLAIM => Some(Channel::Claim),
+        REGION => Some(Channel::Region),
+        0..=2 if config.low_channels() => Some(Channel::Other(id)),
+        _ => None,
+    })
+}
+
+// policies added to the game later have no wording here yet and are bridged generically.
+fn as_policy(policy: &UserModerationPolicy) -> Option<&'static str> {
+    #[allow(unreachable_patterns)]
+    match policy {
+        PermanentBlockLogin | TemporaryBlockLogin => Some("logging in"),
+        BlockChat => Some("chatting"),
+        BlockConstruct => Some("building"),
+        _ => None,
+    }
+}
+
+// a cache miss still bridges the message, showing the unresolved id instead of the name.
+fn resolve(name: Option<&str>, id: u64, channel_id: i32, config: &Config) -> String {
+    name.map_or_else(|| {
+        tracing::debug!(target_id = id, channel_id, "unresolved chat target");
+        config.unresolved_name(id)
+    }, str::to_string)
+}
+
+// the end of a temporary ban both as a date and as "in 3 days", permanent bans ignore their expiration time.
+fn as_expiry(policy: &UserModerationPolicy, expiry: Timestamp) -> String {
+    match policy {
+        PermanentBlockLogin => "permanently".to_string(),
+        _ => {
+            let secs = unix_secs(expiry);
+            format!("until <t:{}:f> (<t:{}:R>)", secs, secs)
+        }
+    }
+}
+
+// rounds to the nearest second, euclidean so timestamps before the epoch round the same way.
+pub fn unix_secs(timestamp: Timestamp) -> i64 {
+    (timestamp.to_micros_since_unix_epoch() + 500_000).div_euclid(1_000_000)
+}
+
+// the whole second a timestamp falls in, for bounds that must not skip ahead the way rounding does.
+pub fn floor_secs(timestamp: Timestamp) -> i64 {
+    timestamp.to_micros_since_unix_epoch().div_euclid(1_000_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ban(user: &str, policy: &str) -> Message { Message::moderation(user.to_string(), policy, "permanently", None) }
+
+    fn content(msg: Message) -> String {
+        match msg.plain() {
+            Message::Chat { content, .. } => content,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn words_known_policies() {
+        assert_eq!(as_policy(&PermanentBlockLogin), Some("logging in"));
+        assert_eq!(as_policy(&TemporaryBlockLogin), Some("logging in"));
+        assert_eq!(as_policy(&BlockChat), Some("chatting"));
+        assert_eq!(as_policy(&BlockConstruct), Some("building"));
+    }
+
+    #[test]
+    fn formats_expiry() {
+        let expiry = at(1_700_000_000_400_000);
+        assert_eq!(as_expiry(&PermanentBlockLogin, expiry), "permanently");
+        assert_eq!(as_expiry(&TemporaryBlockLogin, expiry), "until <t:1700000000:f> (<t:1700000000:R>)");
+        assert_eq!(as_expiry(&BlockChat, expiry), "until <t:1700000000:f> (<t:1700000000:R>)");
+    }
+
+    fn at(micros: i64) -> Timestamp { Timestamp::from_micros_since_unix_epoch(micros) }
+
+    #[test]
+    fn rounds_to_the_nearest_second() {
+        assert_eq!(unix_secs(at(1_700_000_000_000_000)), 1_700_000_000);
+        assert_eq!(unix_secs(at(1_700_000_000_499_999)), 1_700_000_000);
+        assert_eq!(unix_secs(at(1_700_000_000_500_000)), 1_700_000_001);
+        assert_eq!(unix_secs(at(1_700_000_000_999_999)), 1_700_000_001);
+        assert_eq!(unix_secs(at(0)), 0);
+        assert_eq!(unix_secs(at(-500_000)), 0);
+        assert_eq!(unix_secs(at(-500_001)), -1);
+    }
+
+    #[test]
+    fn floors_to_the_second() {
+        assert_eq!(floor_secs(at(1_700_000_000_000_000)), 1_700_000_000);
+        assert_eq!(floor_secs(at(1_700_000_000_999_999)), 1_700_000_000);
+        assert_eq!(floor_secs(at(-1)), -1);
+    }
+
+    #[test]
+    fn falls_back_on_cache_miss() {
+        let config = Config::default();
+        assert_eq!(resolve(Some("Hearthwood"), 42, REGION, &config), "Hearthwood");
+        assert_eq!(resolve(None, 42, REGION, &config), "42");
+
+        let mut names = Names::default();
+        assert_eq!(player(&names, 7, &config), "Player #7");
+        names.players.insert(7, "Alice".to_string());
+        assert_eq!(player(&names, 7, &config), "Alice");
+    }
+
+    #[test]
+    fn orders_shuffled_inserts() {
+        let inserts = vec![(12, "c"), (10, "a"), (12, "d"), (11, "b"), (10, "a2"), (13, "e"), (12, "d2")];
+        let ordered = chronological(inserts, |(timestamp, _)| *timestamp);
+        // the same second keeps the order the sdk delivered it in.
+        assert_eq!(ordered, [(10, "a"), (10, "a2"), (11, "b"), (12, "c"), (12, "d"), (12, "d2"), (13, "e")]);
+    }
+
+    #[test]
+    fn names_empty_usernames() {
+        let config = Config::default();
+        assert_eq!(named("Alice".to_string(), &config).as_deref(), Some("Alice"));
+        assert_eq!(named(String::new(), &config).as_deref(), Some("Server"));
+        assert_eq!(named(" \t\n".to_string(), &config).as_deref(), Some("Server"));
+
+        let config: Config = toml::from_str("empty_username = \"NPC\"").unwrap();
+        assert_eq!(named("   ".to_string(), &config).as_deref(), Some("NPC"));
+    }
+
+    #[test]
+    fn drops_empty_usernames_when_asked() {
+        let config: Config = toml::from_str("drop_empty_username = true").unwrap();
+        assert_eq!(named(String::new(), &config), None);
+        assert_eq!(named("  ".to_string(), &config), None);
+        assert_eq!(named(" Alice ".to_string(), &config).as_deref(), Some(" Alice "));
+    }
+
+    #[test]
+    fn maps_channel_ids() {
+        let config = Config::default();
+        assert_eq!(as_channel(REGION, &config), Some(Channel::Region));
+        assert_eq!(as_channel(CLAIM, &config), Some(Channel::Claim));
+        assert_eq!(as_channel(EMPIRE_PUBLIC, &config), Some(Channel::EmpirePublic));
+        assert_eq!(as_channel(EMPIRE_INTERNAL, &config), Some(Channel::EmpireInternal));
+        assert_eq!(as_channel(1, &config), None);
+        assert_eq!(as_channel(99, &config), None);
+
+        let config: Config = toml::from_str("low_channels = true\n[[channel_ids]]\nid = 99\nkind = \"claim\"").unwrap();
+        assert_eq!(as_channel(1, &config), Some(Channel::Other(1)));
+        assert_eq!(as_channel(99, &config), Some(Channel::Claim));
+    }
+
+    #[test]
+    fn leaves_small_waves_alone() {
+        let bans = collapse(vec![ban("Alice", "logging in"), ban("Bob", "logging in")], 2);
+        assert_eq!(bans.len(), 2);
+        assert!(bans.iter().all(|msg| matches!(msg, Message::Moderation { .. })));
+    }
+
+    #[test]
+    fn collapses_waves_per_policy() {
+        let bans = vec![
+            ban("Alice", "logging in"),
+            ban("Bob", "chatting"),
+            ban("Carol", "logging in"),
+            ban("Dave", "logging in"),
+            Message::unban("Eve".to_string(), "logging in"),
+        ];
+        let mut messages = collapse(bans, 2);
+        assert_eq!(messages.len(), 3);
+
+        // anything but a ban keeps its place ahead of the summaries.
+        assert!(matches!(&messages[0], Message::Moderation { kind: ModerationKind::Unban, .. }));
+        let summary = content(messages.remove(1));
+        let lines = summary.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "3 players have been banned from logging in!");
+        assert!(lines[1].contains("Alice") && lines[2].contains("Carol"));
+        assert_eq!(lines[3], "...and 1 more.");
+        assert!(matches!(&messages[1], Message::Moderation { user, .. } if user == "Bob"));
+    }
+}
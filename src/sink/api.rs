@@ -0,0 +1,114 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, watch};
+use crate::glue::Config;
+use crate::message::Message;
+use super::Sink;
+
+const STREAM_CAPACITY: usize = 256;
+
+struct Recent {
+    messages: Mutex<VecDeque<Message>>,
+    capacity: usize,
+    live:     broadcast::Sender<Message>,
+}
+
+// keeps the last messages for /messages and pushes every new one to /stream, for widgets and dashboards.
+pub struct ApiSink {
+    recent: Arc<Recent>,
+}
+
+impl ApiSink {
+    pub fn new(config: watch::Receiver<Config>) -> Self {
+        let (addr, capacity) = {
+            let config = config.borrow();
+            (config.api_addr().to_string(), config.api_buffer())
+        };
+        let recent = Arc::new(Recent {
+            messages: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            live: broadcast::channel(STREAM_CAPACITY).0,
+        });
+
+        tokio::spawn(serve(addr, recent.clone()));
+        Self { recent }
+    }
+}
+
+#[async_trait]
+impl Sink for ApiSink {
+    fn name(&self) -> &str { "api" }
+
+    async fn send(&self, msg: &Message) -> Result<()> {
+        {
+            let mut messages = self.recent.messages.lock().unwrap();
+            if messages.len() >= self.recent.capacity { messages.pop_front(); }
+            if self.recent.capacity > 0 { messages.push_back(msg.clone()); }
+        }
+        // nobody listening is not an error.
+        let _ = self.recent.live.send(msg.clone());
+        Ok(())
+    }
+}
+
+async fn serve(addr: String, recent: Arc<Recent>) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => { tracing::error!(addr, error = %e, "failed to bind api server"); return }
+    };
+    tracing::info!(addr, "serving messages");
+
+    loop {
+        let Ok((stream, _)) = listener.accept().await else { continue };
+        tokio::spawn(handle(stream, recent.clone()));
+    }
+}
+
+async fn handle(mut stream: TcpStream, recent: Arc<Recent>) {
+    let mut request = [0u8; 1024];
+    let Ok(read) = stream.read(&mut request).await else { return };
+    let request = String::from_utf8_lossy(&request[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let path = path.split('?').next().unwrap_or(path);
+
+    match path {
+        "/messages" => {
+            let body = {
+                let messages = recent.messages.lock().unwrap();
+                serde_json::to_string(&*messages).unwrap_or_else(|_| "[]".to_string())
+            };
+            let _ = stream.write_all(response("200 OK", "application/json", &body).as_bytes()).await;
+        }
+        "/stream" => {
+            let mut live = recent.live.subscribe();
+            let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+                        Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n";
+            if stream.write_all(head.as_bytes()).await.is_err() { return }
+
+            loop {
+                let msg = match live.recv().await {
+                    Ok(msg) => msg,
+                    // a slow client misses what it could not keep up with, but stays connected.
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                let Ok(data) = serde_json::to_string(&msg) else { continue };
+                if stream.write_all(format!("data: {}\n\n", data).as_bytes()).await.is_err() { return }
+            }
+        }
+        _ => {
+            let _ = stream.write_all(response("404 Not Found", "text/plain", "not found\n").as_bytes()).await;
+        }
+    }
+}
+
+fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body,
+    )
+}
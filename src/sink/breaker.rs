@@ -0,0 +1,54 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen,
+}
+
+// stops hammering an endpoint that keeps failing: after enough failures in a row nothing is sent until the
+// cooldown is over, then a single batch decides whether the sink recovered.
+pub struct Breaker {
+    state: State,
+}
+
+impl Breaker {
+    pub fn new() -> Self { Self { state: State::Closed { failures: 0 } } }
+
+    pub fn is_open(&self) -> bool { matches!(self.state, State::Open { .. }) }
+
+    // whether a send may go out now, moving an open circuit to half-open once its cooldown is over.
+    pub fn allows(&mut self) -> bool {
+        match self.state {
+            State::Open { until } if Instant::now() < until => false,
+            State::Open { .. } => {
+                tracing::info!("circuit half-open, testing whether the sink recovered");
+                self.state = State::HalfOpen;
+                true
+            }
+            _ => true,
+        }
+    }
+
+    // a threshold of zero never opens the circuit.
+    pub fn record(&mut self, ok: bool, threshold: u32, cooldown: Duration) {
+        self.state = match (&self.state, ok) {
+            (State::HalfOpen, true) => {
+                tracing::info!("circuit closed, sink recovered");
+                State::Closed { failures: 0 }
+            }
+            (_, true) => State::Closed { failures: 0 },
+            (State::HalfOpen, false) => {
+                tracing::warn!(cooldown_secs = cooldown.as_secs(), "circuit open again, sink still failing");
+                State::Open { until: Instant::now() + cooldown }
+            }
+            (State::Closed { failures }, false) if threshold > 0 && failures + 1 >= threshold => {
+                tracing::warn!(failures = failures + 1, cooldown_secs = cooldown.as_secs(), "circuit open, pausing sends");
+                State::Open { until: Instant::now() + cooldown }
+            }
+            (State::Closed { failures }, false) => State::Closed { failures: failures + 1 },
+            (State::Open { until }, false) => State::Open { until: *until },
+        };
+    }
+}
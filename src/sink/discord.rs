@@ -0,0 +1,454 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use tokio::sync::{watch, Mutex};
+use crate::glue::{Config, Format, Oversize, TagPosition};
+use crate::message::{Channel, Message};
+use crate::queue::{Entry, Queue};
+use crate::webhook::{Rejected, Webhook};
+use super::Sink;
+
+const MAX_EMBEDS: usize = 10;
+const MAX_CONTENT: usize = 2000;
+
+// suppresses every ping (@everyone, @here, roles, users) without touching the text itself.
+#[derive(Clone, Default, serde::Serialize)]
+struct AllowedMentions {
+    parse: [&'static str; 0],
+}
+
+#[derive(Clone, serde::Serialize)]
+struct Author {
+    name: String,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct Embed {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author:      Option<Author>,
+    description: String,
+    color:       u32,
+}
+
+#[derive(Clone, Default, serde::Serialize)]
+struct Payload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avatar_url: Option<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    content:  String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    embeds:   Vec<Embed>,
+    allowed_mentions: AllowedMentions,
+}
+
+struct Chat {
+    username: String,
+    tag:      Option<String>,
+    content:  String,
+    channel:  Channel,
+    channel_id: Option<i32>,
+    timestamp: Option<i64>,
+}
+
+impl Chat {
+    // discord renders the username override verbatim, only text inside content needs escaping.
+    fn text(&self, config: &Config) -> String {
+        match self.timestamp.filter(|_| config.show_timestamp()) {
+            Some(secs) => format!("<t:{}:t> {}", secs, escape_markdown(&self.content)),
+            None => escape_markdown(&self.content),
+        }
+    }
+
+    fn display_name(&self, config: &Config) -> String {
+        let prefix = config.channel_prefix(self.channel, self.channel_id);
+        format!("{}{}", prefix, config.author(self.channel, &self.username, self.tag.as_deref()))
+    }
+
+    // where the message came from, without the player, for when the player is the webhook identity.
+    fn origin(&self, config: &Config) -> String {
+        let prefix = config.channel_prefix(self.channel, self.channel_id);
+        let tag = self.tag.as_ref().filter(|_| self.channel == Channel::Region || config.tag_position() != TagPosition::None);
+        match tag {
+            Some(tag) => format!("{}[{}]", prefix, tag),
+            None => prefix.trim_end().to_string(),
+        }
+    }
+}
+
+impl Payload {
+    fn chat(username: String, content: String) -> Self {
+        Self { username: Some(username), content, ..Self::default() }
+    }
+
+    fn embeds(embeds: Vec<Embed>) -> Self { Self { embeds, ..Self::default() } }
+
+    fn player(chat: Chat, config: &Config) -> Self {
+        let origin = chat.origin(config);
+        let username = Some(chat.username);
+
+        match config.format() {
+            Format::Content => {
+                let content = if origin.is_empty() {
+                    chat.text(config)
+                } else {
+                    format!("{} {}", escape_markdown(&origin), chat.text(config))
+                };
+                Self { username, content, ..Self::default() }
+            }
+            Format::Embed => {
+                let embed = Embed {
+                    author: Some(Author { name: origin }).filter(|author| !author.name.is_empty()),
+                    description: chat.text(config),
+                    color: config.color(chat.channel),
+                };
+                Self { username, embeds: vec![embed], ..Self::default() }
+            }
+        }
+    }
+
+    fn batch(messages: Vec<Message>, config: &Config) -> Vec<Self> {
+        let mut chats = messages
+            .into_iter()
+            .filter_map(|msg| match msg.plain() {
+                Message::Chat { username, tag, content, channel, channel_id, timestamp, .. } =>
+                    Some(Chat { username, tag, content, channel, channel_id, timestamp }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if chats.is_empty() { return Vec::new() }
+
+        // every message has to be posted on its own when it is posted as its player.
+        if config.player_identity() {
+            let mut payloads = chats.into_iter().map(|chat| Self::player(chat, config)).collect::<Vec<_>>();
+            for payload in &mut payloads { payload.avatar_url = config.webhook_avatar_url().map(str::to_string); }
+            return payloads.into_iter().flat_map(|payload| payload.fit(config)).collect()
+        }
+
+        let mut payloads = match config.format() {
+            // a single message keeps the username override, a batch lists every author inline.
+            Format::Content if chats.len() == 1 && config.webhook_username().is_none() => {
+                let chat = chats.pop().unwrap();
+                vec![Self::chat(chat.display_name(config), chat.text(config))]
+            }
+            Format::Content => {
+                let content = chats
+                    .iter()
+                    .map(|chat| format!("**{}**: {}", escape_markdown(&chat.display_name(config)), chat.text(config)))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                vec![Self { content, ..Self::default() }]
+            }
+            Format::Embed => {
+                let embeds = chats
+                    .into_iter()
+                    .map(|chat| Embed {
+                        author: Some(Author { name: chat.display_name(config) }),
+                        description: chat.text(config),
+                        color: config.color(chat.channel),
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut payloads = Vec::new();
+                let mut embeds = embeds.into_iter().peekable();
+                while embeds.peek().is_some() {
+                    payloads.push(Self::embeds(embeds.by_ref().take(MAX_EMBEDS).collect()));
+                }
+                payloads
+            }
+        };
+
+        for payload in &mut payloads {
+            if let Some(username) = config.webhook_username() { payload.username = Some(username.to_string()); }
+            payload.avatar_url = config.webhook_avatar_url().map(str::to_string);
+        }
+        payloads.into_iter().flat_map(|payload| payload.fit(config)).collect()
+    }
+
+    // discord rejects content over the limit outright, so it is split or cut down beforehand.
+    fn fit(self, config: &Config) -> Vec<Self> {
+        if self.content.chars().count() <= MAX_CONTENT { return vec![self] }
+
+        match config.oversize() {
+            Oversize::Truncate => {
+                let mut content = split(&self.content, MAX_CONTENT - 1).swap_remove(0);
+                content.push('…');
+                vec![Self { content, ..self }]
+            }
+            Oversize::Split => split(&self.content, MAX_CONTENT)
+                .into_iter()
+                .map(|content| Self { content, ..self.clone() })
+                .collect(),
+        }
+    }
+}
+
+// splits on the last line break or space within the limit, counting chars so no codepoint is cut.
+fn split(content: &str, limit: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut rest = content;
+
+    while rest.chars().count() > limit {
+        let end = rest.char_indices().nth(limit).map_or(rest.len(), |(i, _)| i);
+        let head = &rest[..end];
+
+        let cut = match head.rfind('\n').or_else(|| head.rfind(' ')).filter(|&i| i > 0) {
+            Some(cut) => cut,
+            // a hard cut must not separate an escaping backslash from the character it escapes.
+            None if (head.len() - head.trim_end_matches('\\').len()) % 2 == 1 => end - 1,
+            None => end,
+        };
+
+        pieces.push(rest[..cut].to_string());
+        rest = rest[cut..].trim_start_matches(['\n', ' ']);
+    }
+
+    if !rest.is_empty() { pieces.push(rest.to_string()); }
+    pieces
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '~' | '`' | '|') { escaped.push('\\'); }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub struct DiscordWebhookSink {
+    config:  watch::Receiver<Config>,
+    webhook: Mutex<Webhook>,
+    queue:   Mutex<Option<Queue>>,
+}
+
+impl DiscordWebhookSink {
+    pub fn new(config: watch::Receiver<Config>) -> Self {
+        let (webhook, queue) = {
+            let config = config.borrow();
+            let webhook = Webhook::new().dry_run(config.dry_run()).timeouts(config.timeouts());
+            let queue = config.queue_path()
+                .map(|path| Queue::open(path, config.queue_capacity()))
+                .transpose()
+                .unwrap_or_else(|e| { tracing::error!(error = %e, "failed to open queue, continuing without"); None });
+            (webhook, queue)
+        };
+
+        Self { config, webhook: Mutex::new(webhook), queue: Mutex::new(queue) }
+    }
+
+    // chat and moderation go to their own webhooks, chat split further by claim or empire route and by the thread
+    // its channel posts to.
+    fn routes(&self, batch: &[Message]) -> (Vec<(Vec<String>, Vec<Payload>)>, u32) {
+        let config = self.config.borrow();
+        let (moderation, chat): (Vec<_>, Vec<_>) = batch.iter().cloned().partition(Message::is_moderation);
+
+        let mut destinations: Vec<(Vec<String>, Vec<Message>)> = Vec::new();
+        for msg in chat {
+            let channel = msg.channel().unwrap_or(Channel::Region);
+            let webhook_urls = in_thread(config.chat_webhook_urls(channel, msg.tag()), config.thread_id(channel));
+            match destinations.iter_mut().find(|(other, _)| *other == webhook_urls) {
+                Some((_, messages)) => messages.push(msg),
+                None => destinations.push((webhook_urls, vec![msg])),
+            }
+        }
+
+        let mut routes = destinations
+            .into_iter()
+            .map(|(webhook_urls, messages)| (webhook_urls, Payload::batch(messages, &config)))
+            .collect::<Vec<_>>();
+        let thread = config.thread_id(Channel::Moderation);
+        routes.push((in_thread(config.moderation_webhook_urls(), thread), Payload::batch(moderation, &config)));
+        (routes, config.max_attempts())
+    }
+}
+
+#[async_trait]
+impl Sink for DiscordWebhookSink {
+    fn name(&self) -> &str { "discord" }
+
+    async fn send(&self, msg: &Message) -> Result<()> { self.send_batch(std::slice::from_ref(msg)).await }
+
+    async fn send_batch(&self, batch: &[Message]) -> Result<()> {
+        let (routes, max_attempts) = self.routes(batch);
+
+        let mut webhook = self.webhook.lock().await;
+        let mut queue = self.queue.lock().await;
+
+        let mut failed = 0;
+        let mut total = 0;
+        for (webhook_urls, payloads) in &routes {
+            total += payloads.len() * webhook_urls.len();
+            for payload in payloads {
+                // one payload that fails to serialize is dropped and counted, the rest of the batch still goes out.
+                let payload = match serde_json::to_string(payload) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        failed += webhook_urls.len();
+                        tracing::error!(error = %e, "failed to serialize payload, dropping it");
+                        continue
+                    }
+                };
+                for (i, webhook_url) in webhook_urls.iter().enumerate() {
+                    if let Err(e) = webhook.post(webhook_url, &payload, max_attempts).await {
+                        failed += 1;
+                        tracing::warn!(webhook = i, error = %e, "failed to send message to webhook");
+                        if let Some(queue) = queue.as_mut() && !e.is::<Rejected>() {
+                            let entry = Entry { url: webhook_url.clone(), payload: payload.clone() };
+                            if let Err(e) = queue.push(entry) { tracing::error!(error = %e, "failed to queue message"); }
+                        }
+                    }
+                }
+            }
+        }
+
+        if failed < total && let Some(queue) = queue.as_mut() && !queue.is_empty() {
+            drain(&mut webhook, queue, max_attempts).await;
+        }
+
+        match failed {
+            0 => Ok(()),
+            _ => Err(anyhow!("{} of {} deliveries failed", failed, total)),
+        }
+    }
+
+    async fn hold(&self, batch: &[Message]) -> bool {
+        let mut queue = self.queue.lock().await;
+        let Some(queue) = queue.as_mut() else { return false };
+
+        let (routes, _) = self.routes(batch);
+        for (webhook_urls, payloads) in &routes {
+            for payload in payloads {
+                let Ok(payload) = serde_json::to_string(payload) else { continue };
+                for webhook_url in webhook_urls {
+                    let entry = Entry { url: webhook_url.clone(), payload: payload.clone() };
+                    if let Err(e) = queue.push(entry) { tracing::error!(error = %e, "failed to queue message"); }
+                }
+            }
+        }
+        true
+    }
+
+    async fn retry(&self) {
+        let mut queue = self.queue.lock().await;
+        let Some(queue) = queue.as_mut().filter(|queue| !queue.is_empty()) else { return };
+
+        let max_attempts = self.config.borrow().max_attempts();
+        drain(&mut *self.webhook.lock().await, queue, max_attempts).await;
+    }
+}
+
+// discord posts into a thread of a forum channel's webhook when asked to by the query.
+fn in_thread(webhook_urls: Vec<String>, thread: Option<&str>) -> Vec<String> {
+    let Some(thread) = thread else { return webhook_urls };
+    webhook_urls
+        .into_iter()
+        .map(|url| match reqwest::Url::parse(&url) {
+            Ok(mut url) => {
+                url.query_pairs_mut().append_pair("thread_id", thread);
+                url.to_string()
+            }
+            Err(_) => url,
+        })
+        .collect()
+}
+
+async fn drain(webhook: &mut Webhook, queue: &mut Queue, max_attempts: u32) {
+    while let Some(entry) = queue.front() {
+        match webhook.post(&entry.url, &entry.payload, max_attempts).await {
+            Ok(()) => {}
+            Err(e) if e.is::<Rejected>() => tracing::warn!(error = %e, "dropping queued message"),
+            Err(_) => break,
+        }
+        queue.pop_front();
+    }
+
+    if let Err(e) = queue.persist() { tracing::error!(error = %e, "failed to persist queue"); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_code_blocks() {
+        assert_eq!(escape_markdown("```rm -rf```"), "\\`\\`\\`rm -rf\\`\\`\\`");
+        assert_eq!(escape_markdown("`inline`"), "\\`inline\\`");
+    }
+
+    #[test]
+    fn escapes_spoiler_bars() {
+        assert_eq!(escape_markdown("||spoiler||"), "\\|\\|spoiler\\|\\|");
+    }
+
+    #[test]
+    fn escapes_formatting_and_backslashes() {
+        assert_eq!(escape_markdown("**bold** __under__ ~~gone~~"), "\\*\\*bold\\*\\* \\_\\_under\\_\\_ \\~\\~gone\\~\\~");
+        // a backslash of its own must not escape the character after it.
+        assert_eq!(escape_markdown("\\*"), "\\\\\\*");
+        assert_eq!(escape_markdown("plain text"), "plain text");
+    }
+
+    fn payload(content: String) -> Payload { Payload { content, ..Payload::default() } }
+
+    fn config(oversize: &str) -> Config { toml::from_str(&format!("oversize = \"{}\"", oversize)).unwrap() }
+
+    #[test]
+    fn splits_cjk_on_char_boundaries() {
+        let pieces = split(&"漢".repeat(2500), MAX_CONTENT);
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].chars().count(), 2000);
+        assert_eq!(pieces[1].chars().count(), 500);
+    }
+
+    #[test]
+    fn splits_emoji_on_char_boundaries() {
+        assert_eq!(split("😀😀😀", 2), ["😀😀", "😀"]);
+        // a family emoji is several codepoints, counted and cut one codepoint at a time.
+        let family = "👨\u{200d}👩\u{200d}👧";
+        assert_eq!(split(family, 3).concat(), family);
+    }
+
+    #[test]
+    fn prefers_line_breaks_and_spaces() {
+        assert_eq!(split("one two\nthree", 10), ["one two", "three"]);
+        assert_eq!(split("one two three", 10), ["one two", "three"]);
+    }
+
+    #[test]
+    fn keeps_escapes_together_on_a_hard_cut() {
+        let content = format!("{}\\*", "a".repeat(1999));
+        let pieces = split(&content, MAX_CONTENT);
+        assert_eq!(pieces, ["a".repeat(1999).as_str(), "\\*"]);
+
+        // an escaped backslash is a complete pair, cutting after it is fine.
+        let content = format!("{}\\\\x", "a".repeat(1998));
+        let pieces = split(&content, MAX_CONTENT);
+        assert_eq!(pieces[0].chars().count(), 2000);
+        assert_eq!(pieces[1], "x");
+    }
+
+    #[test]
+    fn truncates_to_exactly_the_limit() {
+        let payloads = payload("x".repeat(2500)).fit(&config("truncate"));
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0].content.chars().count(), MAX_CONTENT);
+        assert!(payloads[0].content.ends_with('…'));
+
+        let payloads = payload("漢".repeat(2500)).fit(&config("truncate"));
+        assert_eq!(payloads[0].content.chars().count(), MAX_CONTENT);
+    }
+
+    #[test]
+    fn leaves_content_at_the_limit_alone() {
+        let payloads = payload("漢".repeat(MAX_CONTENT)).fit(&config("truncate"));
+        assert_eq!(payloads[0].content, "漢".repeat(MAX_CONTENT));
+    }
+
+    #[test]
+    fn splits_into_payloads_under_the_limit() {
+        let payloads = payload("😀 ".repeat(1500)).fit(&config("split"));
+        assert_eq!(payloads.len(), 2);
+        assert!(payloads.iter().all(|payload| payload.content.chars().count() <= MAX_CONTENT));
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use tokio::sync::watch;
+use crate::glue::{Config, Rotation};
+use crate::message::Message;
+use super::Sink;
+
+#[derive(Default)]
+struct State {
+    file: Option<File>,
+    day:  Option<NaiveDate>,
+    size: u64,
+}
+
+impl State {
+    fn open(&mut self, path: &str, now: DateTime<Utc>) -> Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        self.size = metadata.len();
+        // an existing file keeps the day it was last written, so a restart still rotates it.
+        self.day = Some(metadata.modified().map_or(now, DateTime::<Utc>::from).date_naive());
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn write(&mut self, config: &Config, now: DateTime<Utc>, line: &str) -> Result<()> {
+        let path = config.file_path();
+        if path == "-" {
+            print!("{}", line);
+            return Ok(())
+        }
+
+        if self.file.is_none() { self.open(path, now)?; }
+
+        let rotated = match config.file_rotation() {
+            Rotation::None => None,
+            Rotation::Daily => self.day
+                .filter(|day| *day != now.date_naive())
+                .map(|day| format!("{}.{}", path, day.format("%Y-%m-%d"))),
+            Rotation::Size if self.size > 0 && self.size + line.len() as u64 > config.file_max_bytes() =>
+                Some(format!("{}.{}", path, now.format("%Y%m%d-%H%M%S"))),
+            Rotation::Size => None,
+        };
+        if let Some(rotated) = rotated {
+            self.file = None;
+            std::fs::rename(path, vacant(rotated))?;
+            self.open(path, now)?;
+        }
+
+        if let Some(file) = self.file.as_mut() { file.write_all(line.as_bytes())?; }
+        self.size += line.len() as u64;
+        self.day = Some(now.date_naive());
+        Ok(())
+    }
+}
+
+// rename replaces whatever it is given, a second rotation within the same second must not overwrite the first.
+fn vacant(rotated: String) -> String {
+    if !Path::new(&rotated).exists() { return rotated }
+    (1..).map(|n| format!("{}.{}", rotated, n)).find(|candidate| !Path::new(candidate).exists()).unwrap_or(rotated)
+}
+
+pub struct FileSink {
+    config: watch::Receiver<Config>,
+    state:  Mutex<State>,
+}
+
+impl FileSink {
+    pub fn new(config: watch::Receiver<Config>) -> Self { Self { config, state: Mutex::new(State::default()) } }
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    fn name(&self) -> &str { "file" }
+
+    async fn send(&self, msg: &Message) -> Result<()> {
+        let Message::Chat { username, tag, content, channel, channel_id, timestamp, seq, .. } = msg.clone().plain() else { return Ok(()) };
+
+        let config = self.config.borrow();
+        let now = Utc::now();
+        let line = format!(
+            "{}{} [{}] {}: {}\n",
+            config.format_time(timestamp.unwrap_or_else(|| now.timestamp())),
+            seq.map(|seq| format!(" #{}", seq)).unwrap_or_default(),
+            config.channel_label(channel, channel_id),
+            config.author(channel, &username, tag.as_deref()),
+            content.replace('\n', " "),
+        );
+
+        self.state.lock().unwrap().write(&config, now, &line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_every_rotation_within_a_second() {
+        let dir = std::env::temp_dir().join(format!("chatbridge-rotation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("chat.log");
+        let toml = format!("file_path = {:?}\nfile_rotation = \"size\"\nfile_max_bytes = 4", path.to_str().unwrap());
+        let config: Config = toml::from_str(&toml).unwrap();
+
+        let (mut state, now) = (State::default(), Utc::now());
+        for line in ["one\n", "two\n", "three\n"] { state.write(&config, now, line).unwrap(); }
+
+        let mut archived = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect::<Vec<_>>();
+        archived.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(archived, ["one\n", "three\n", "two\n"]);
+    }
+}
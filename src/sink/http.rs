@@ -0,0 +1,41 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::{watch, Mutex};
+use crate::glue::Config;
+use crate::message::Message;
+use crate::webhook::Webhook;
+use super::Sink;
+
+// one request per message to an endpoint of the user's choosing, the body laid out by the http_body template.
+pub struct GenericHttpSink {
+    config:  watch::Receiver<Config>,
+    webhook: Mutex<Webhook>,
+}
+
+impl GenericHttpSink {
+    pub fn new(config: watch::Receiver<Config>) -> Self {
+        let webhook = {
+            let config = config.borrow();
+            Webhook::new().dry_run(config.dry_run()).timeouts(config.timeouts())
+        };
+        Self { config, webhook: Mutex::new(webhook) }
+    }
+}
+
+#[async_trait]
+impl Sink for GenericHttpSink {
+    fn name(&self) -> &str { "http" }
+
+    async fn send(&self, msg: &Message) -> Result<()> {
+        let Message::Chat { username, tag, content, channel, channel_id, .. } = msg.clone().plain() else { return Ok(()) };
+
+        let (method, url, headers, body, max_attempts) = {
+            let config = self.config.borrow();
+            let author = config.author(channel, &username, tag.as_deref());
+            let body = config.http_body(&author, &content, &config.channel_label(channel, channel_id));
+            (config.http_method(), config.http_url().to_string(), config.http_headers(), body, config.max_attempts())
+        };
+
+        self.webhook.lock().await.request(method, &url, &headers, &body, max_attempts).await
+    }
+}
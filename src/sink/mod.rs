@@ -0,0 +1,126 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::Instrument;
+use crate::glue::{Config, SinkKind};
+use crate::message::Message;
+use crate::metrics;
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+mod discord;
+pub use discord::DiscordWebhookSink;
+mod slack;
+pub use slack::SlackSink;
+mod telegram;
+pub use telegram::TelegramSink;
+mod file;
+pub use file::FileSink;
+mod api;
+pub use api::ApiSink;
+mod websocket;
+pub use websocket::WebSocketSink;
+mod sqlite;
+pub use sqlite::SqliteSink;
+mod http;
+pub use http::GenericHttpSink;
+mod redis;
+pub use self::redis::RedisSink;
+mod nats;
+pub use nats::NatsSink;
+mod breaker;
+use breaker::Breaker;
+mod outlet;
+pub use outlet::Outlet;
+
+#[async_trait]
+pub trait Sink: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn send(&self, msg: &Message) -> Result<()>;
+
+    // sinks that can merge several messages into a single request override this.
+    async fn send_batch(&self, batch: &[Message]) -> Result<()> {
+        let mut result = Ok(());
+        for msg in batch {
+            if let Err(e) = self.send(msg).await { result = Err(e); }
+        }
+        result
+    }
+
+    // called periodically, so sinks can retry whatever they had to hold back.
+    async fn retry(&self) {}
+
+    // called instead of sending while the circuit is open, sinks that can persist the batch keep it for later.
+    async fn hold(&self, _batch: &[Message]) -> bool { false }
+}
+
+pub type Batch = Arc<[Message]>;
+
+pub fn from_config(config: &watch::Receiver<Config>) -> Vec<Box<dyn Sink>> {
+    let kinds = config.borrow().sinks();
+    kinds
+        .into_iter()
+        .map(|kind| -> Box<dyn Sink> {
+            match kind {
+                SinkKind::Discord => Box::new(DiscordWebhookSink::new(config.clone())),
+                SinkKind::Slack => Box::new(SlackSink::new(config.clone())),
+                SinkKind::Telegram => Box::new(TelegramSink::new(config.clone())),
+                SinkKind::File => Box::new(FileSink::new(config.clone())),
+                SinkKind::Api => Box::new(ApiSink::new(config.clone())),
+                SinkKind::WebSocket => Box::new(WebSocketSink::new(config.clone())),
+                SinkKind::Sqlite => Box::new(SqliteSink::new(config.clone())),
+                SinkKind::Http => Box::new(GenericHttpSink::new(config.clone())),
+                SinkKind::Redis => Box::new(RedisSink::new(config.clone())),
+                SinkKind::Nats => Box::new(NatsSink::new(config.clone())),
+            }
+        })
+        .collect()
+}
+
+// every sink drains its own channel in order, so a slow or failing sink never holds up the others.
+pub fn spawn(sink: Box<dyn Sink>, config: watch::Receiver<Config>) -> (Outlet, JoinHandle<()>) {
+    let (tx, mut rx) = {
+        let config = config.borrow();
+        outlet::bounded(config.message_capacity(), config.overflow())
+    };
+
+    let span = tracing::info_span!("sink", name = sink.name());
+    let task = tokio::spawn(async move {
+        // the first tick fires immediately, letting the sink replay whatever it persisted before new messages go out.
+        let mut retry = tokio::time::interval(RETRY_INTERVAL);
+        retry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut breaker = Breaker::new();
+
+        loop {
+            let batch = tokio::select! {
+                biased;
+                _ = retry.tick() => {
+                    if !breaker.is_open() { sink.retry().await; }
+                    continue
+                }
+                batch = rx.recv() => batch,
+            };
+            let Some(batch) = batch else { break };
+
+            if !breaker.allows() {
+                if !sink.hold(&batch).await { metrics::dropped_many("circuit_open", batch.len() as u64); }
+                continue
+            }
+
+            let result = sink.send_batch(&batch).await;
+            metrics::delivered(sink.name(), result.is_ok());
+            if let Err(e) = &result {
+                tracing::warn!(error = %e, "sink failed");
+            }
+            let (threshold, cooldown) = config.borrow().breaker();
+            breaker.record(result.is_ok(), threshold, cooldown);
+        }
+    }.instrument(span));
+
+    (tx, task)
+}
@@ -0,0 +1,63 @@
+use anyhow::Result;
+use async_nats::{Client, ConnectOptions};
+use async_trait::async_trait;
+use tokio::sync::{watch, Mutex};
+use crate::glue::Config;
+use crate::message::Message;
+use super::Sink;
+
+// publishes every message as json to a nats subject, optionally into a jetstream stream that keeps them.
+pub struct NatsSink {
+    config: watch::Receiver<Config>,
+    client: Mutex<Option<Client>>,
+}
+
+impl NatsSink {
+    pub fn new(config: watch::Receiver<Config>) -> Self { Self { config, client: Mutex::new(None) } }
+}
+
+// async-nats only retries a server it once reached, an unreachable one at startup fails the batch and the next dials again.
+async fn connect(url: &str) -> Result<Client> {
+    let client = ConnectOptions::new()
+        .event_callback(|event| async move { tracing::info!(%event, "nats connection event") })
+        .connect(url)
+        .await?;
+    tracing::info!("connected to nats");
+    Ok(client)
+}
+
+#[async_trait]
+impl Sink for NatsSink {
+    fn name(&self) -> &str { "nats" }
+
+    async fn send(&self, msg: &Message) -> Result<()> { self.send_batch(std::slice::from_ref(msg)).await }
+
+    async fn send_batch(&self, batch: &[Message]) -> Result<()> {
+        let (url, subject, jetstream) = {
+            let config = self.config.borrow();
+            (config.nats_url().expose().to_string(), config.nats_subject().to_string(), config.nats_jetstream())
+        };
+
+        let mut client = self.client.lock().await;
+        if client.is_none() { *client = Some(connect(&url).await?); }
+        let Some(client) = client.as_ref() else { return Ok(()) };
+
+        if jetstream {
+            let context = async_nats::jetstream::new(client.clone());
+            for msg in batch {
+                let json = serde_json::to_string(msg)?;
+                // the first await hands the message over, the second waits for the stream to store it.
+                context.publish(subject.clone(), json.into()).await?.await?;
+            }
+            return Ok(())
+        }
+
+        for msg in batch {
+            let json = serde_json::to_string(msg)?;
+            client.publish(subject.clone(), json.into()).await?;
+        }
+        // plain publishes are only buffered, a failed flush makes the batch count as failed.
+        client.flush().await?;
+        Ok(())
+    }
+}
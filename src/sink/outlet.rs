@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use crate::glue::Overflow;
+use crate::metrics;
+use super::Batch;
+
+#[derive(Default)]
+struct Queue {
+    batches: VecDeque<Batch>,
+    // messages, not batches, so capacity means the same here as for the outbox.
+    len:     usize,
+    closed:  bool,
+}
+
+impl Queue {
+    fn fits(&self, batch: &Batch, capacity: usize) -> bool { self.len == 0 || self.len + batch.len() <= capacity }
+}
+
+struct Shared {
+    queue:    Mutex<Queue>,
+    capacity: usize,
+    overflow: Overflow,
+    // one waiter on either side, notify_one keeps a permit so a wakeup between check and wait is not lost.
+    ready:    Notify,
+    space:    Notify,
+}
+
+// the queue in front of one sink, bounded like the outbox and overflowing by the same policy, so a sink that is
+// slow without failing can not grow it forever.
+pub struct Outlet {
+    shared: Arc<Shared>,
+}
+
+pub struct Inlet {
+    shared: Arc<Shared>,
+}
+
+pub fn bounded(capacity: usize, overflow: Overflow) -> (Outlet, Inlet) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue::default()),
+        capacity: capacity.max(1),
+        overflow,
+        ready: Notify::new(),
+        space: Notify::new(),
+    });
+    (Outlet { shared: shared.clone() }, Inlet { shared })
+}
+
+impl Outlet {
+    // waits for room when blocking, otherwise makes room by dropping the oldest batches. a batch larger than the
+    // whole capacity still goes into an empty queue.
+    pub async fn send(&self, batch: Batch) {
+        let shared = &self.shared;
+        loop {
+            {
+                let mut queue = shared.queue.lock().unwrap();
+                if !queue.fits(&batch, shared.capacity) && shared.overflow == Overflow::DropOldest {
+                    let mut dropped = 0;
+                    while !queue.fits(&batch, shared.capacity) {
+                        let Some(oldest) = queue.batches.pop_front() else { break };
+                        queue.len -= oldest.len();
+                        dropped += oldest.len();
+                    }
+                    tracing::warn!(dropped, "sink queue full, dropped oldest messages");
+                    metrics::dropped_many("overflow", dropped as u64);
+                }
+                if queue.fits(&batch, shared.capacity) {
+                    queue.len += batch.len();
+                    queue.batches.push_back(batch);
+                    shared.ready.notify_one();
+                    return
+                }
+            }
+            shared.space.notified().await;
+        }
+    }
+}
+
+// dropping the outlet lets the sink drain what is queued, then its task ends.
+impl Drop for Outlet {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.ready.notify_one();
+    }
+}
+
+impl Inlet {
+    pub async fn recv(&mut self) -> Option<Batch> {
+        let shared = &self.shared;
+        loop {
+            {
+                let mut queue = shared.queue.lock().unwrap();
+                if let Some(batch) = queue.batches.pop_front() {
+                    queue.len -= batch.len();
+                    shared.space.notify_one();
+                    return Some(batch)
+                }
+                if queue.closed { return None }
+            }
+            shared.ready.notified().await;
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use redis::AsyncCommands;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+use crate::glue::Config;
+use crate::message::Message;
+use super::Sink;
+
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+// publishes every message as json to a redis channel, for services that would rather subscribe than poll.
+pub struct RedisSink {
+    config:     watch::Receiver<Config>,
+    connection: Mutex<Option<ConnectionManager>>,
+}
+
+impl RedisSink {
+    pub fn new(config: watch::Receiver<Config>) -> Self { Self { config, connection: Mutex::new(None) } }
+}
+
+// the manager reconnects on its own with backoff once established, the first connection is retried per batch.
+async fn connect(url: &str) -> Result<ConnectionManager> {
+    let client = redis::Client::open(url)?;
+    let config = ConnectionManagerConfig::new().set_max_delay(MAX_DELAY.as_millis() as u64);
+    let connection = ConnectionManager::new_with_config(client, config).await?;
+    tracing::info!("connected to redis");
+    Ok(connection)
+}
+
+#[async_trait]
+impl Sink for RedisSink {
+    fn name(&self) -> &str { "redis" }
+
+    async fn send(&self, msg: &Message) -> Result<()> { self.send_batch(std::slice::from_ref(msg)).await }
+
+    async fn send_batch(&self, batch: &[Message]) -> Result<()> {
+        let (url, channel) = {
+            let config = self.config.borrow();
+            (config.redis_url().expose().to_string(), config.redis_channel().to_string())
+        };
+
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() { *connection = Some(connect(&url).await?); }
+        let Some(connection) = connection.as_mut() else { return Ok(()) };
+
+        for msg in batch {
+            let json = serde_json::to_string(msg)?;
+            let _: () = connection.publish(&channel, json).await?;
+        }
+        Ok(())
+    }
+}
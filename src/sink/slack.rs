@@ -0,0 +1,83 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use tokio::sync::{watch, Mutex};
+use crate::glue::{Config, SinkKind};
+use crate::message::Message;
+use crate::webhook::Webhook;
+use super::Sink;
+
+#[derive(serde::Serialize)]
+struct Payload {
+    text: String,
+}
+
+impl Payload {
+    fn batch(batch: &[Message], config: &Config) -> Self {
+        let text = batch
+            .iter()
+            .filter_map(|msg| match msg.clone().plain() {
+                Message::Chat { username, tag, content, channel, channel_id, .. } => {
+                    let prefix = config.channel_prefix(channel, channel_id);
+                    let name = format!("{}{}", prefix, config.author(channel, &username, tag.as_deref()));
+                    Some(format!("*{}*: {}", escape(&name), escape(&content)))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Self { text }
+    }
+}
+
+// slack only parses mentions and links in <...>, escaping the brackets neutralizes <!channel> and friends.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+pub struct SlackSink {
+    config:  watch::Receiver<Config>,
+    webhook: Mutex<Webhook>,
+}
+
+impl SlackSink {
+    pub fn new(config: watch::Receiver<Config>) -> Self {
+        let webhook = {
+            let config = config.borrow();
+            Webhook::new().dry_run(config.dry_run()).timeouts(config.timeouts())
+        };
+        Self { config, webhook: Mutex::new(webhook) }
+    }
+}
+
+#[async_trait]
+impl Sink for SlackSink {
+    fn name(&self) -> &str { "slack" }
+
+    async fn send(&self, msg: &Message) -> Result<()> { self.send_batch(std::slice::from_ref(msg)).await }
+
+    async fn send_batch(&self, batch: &[Message]) -> Result<()> {
+        let (webhook_urls, max_attempts, payload) = {
+            let config = self.config.borrow();
+            (config.webhook_urls(SinkKind::Slack), config.max_attempts(), Payload::batch(batch, &config))
+        };
+        if webhook_urls.is_empty() || payload.text.is_empty() {
+            return Ok(());
+        }
+
+        let payload = serde_json::to_string(&payload)?;
+        let mut webhook = self.webhook.lock().await;
+
+        let mut failed = 0;
+        for (i, webhook_url) in webhook_urls.iter().enumerate() {
+            if let Err(e) = webhook.post(webhook_url, &payload, max_attempts).await {
+                failed += 1;
+                tracing::warn!(webhook = i, error = %e, "failed to send message to webhook");
+            }
+        }
+
+        match failed {
+            0 => Ok(()),
+            _ => Err(anyhow!("{} of {} deliveries failed", failed, webhook_urls.len())),
+        }
+    }
+}
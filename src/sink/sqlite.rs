@@ -0,0 +1,88 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use rusqlite::{params, Connection};
+use std::sync::Mutex;
+use tokio::sync::watch;
+use crate::glue::Config;
+use crate::message::Message;
+use super::Sink;
+
+// every entry brings the schema from the previous version to the next, user_version records how far a database got.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE messages (
+        id        INTEGER PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        region    TEXT NOT NULL,
+        channel   TEXT NOT NULL,
+        username  TEXT NOT NULL,
+        target    TEXT,
+        content   TEXT NOT NULL
+    );
+    CREATE INDEX messages_timestamp ON messages (timestamp);",
+    "ALTER TABLE messages ADD COLUMN seq INTEGER;",
+    // target holds the resolved claim or empire name, target_id the raw id it was resolved from.
+    "ALTER TABLE messages ADD COLUMN target_id INTEGER;",
+];
+
+const INSERT: &str =
+    "INSERT INTO messages (timestamp, region, channel, username, target, target_id, content, seq)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)";
+
+// keeps every bridged message in a table, for searching the history later.
+pub struct SqliteSink {
+    config: watch::Receiver<Config>,
+    db:     Mutex<Option<Connection>>,
+}
+
+impl SqliteSink {
+    pub fn new(config: watch::Receiver<Config>) -> Self {
+        let path = config.borrow().sqlite_path().to_string();
+        let db = open(&path)
+            .inspect_err(|e| tracing::error!(path, error = %e, "failed to open sqlite database, dropping messages"))
+            .ok();
+        Self { config, db: Mutex::new(db) }
+    }
+}
+
+fn open(path: &str) -> Result<Connection> {
+    let db = Connection::open(path)?;
+    // readers can query the history while the bridge keeps writing.
+    db.pragma_update(None, "journal_mode", "WAL")?;
+
+    let version: usize = db.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        db.execute_batch(migration)?;
+        db.pragma_update(None, "user_version", i + 1)?;
+        tracing::info!(version = i + 1, "migrated sqlite database");
+    }
+    Ok(db)
+}
+
+#[async_trait]
+impl Sink for SqliteSink {
+    fn name(&self) -> &str { "sqlite" }
+
+    async fn send(&self, msg: &Message) -> Result<()> { self.send_batch(std::slice::from_ref(msg)).await }
+
+    // a batch goes in as one transaction, the statement is prepared once and cached by the connection.
+    async fn send_batch(&self, batch: &[Message]) -> Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let Some(db) = db.as_mut() else { return Ok(()) };
+        let config = self.config.borrow();
+
+        let tx = db.transaction()?;
+        {
+            let mut insert = tx.prepare_cached(INSERT)?;
+            for msg in batch {
+                let Message::Chat { username, tag, content, channel, channel_id, target_id, timestamp, seq } = msg.clone().plain() else {
+                    continue
+                };
+                let timestamp = timestamp.unwrap_or_else(|| chrono::Utc::now().timestamp());
+                let channel = config.channel_label(channel, channel_id);
+                insert.execute(params![timestamp, config.region(), channel.as_ref(), username, tag, target_id, content, seq])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
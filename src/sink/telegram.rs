@@ -0,0 +1,79 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+use crate::glue::Config;
+use crate::message::Message;
+use crate::webhook::Webhook;
+use super::Sink;
+
+// telegram allows about 30 messages per second per bot.
+const SEND_INTERVAL: Duration = Duration::from_millis(34);
+
+#[derive(serde::Serialize)]
+struct Payload<'a> {
+    chat_id:    &'a str,
+    text:       String,
+    parse_mode: &'static str,
+}
+
+fn render(batch: &[Message], config: &Config) -> String {
+    batch
+        .iter()
+        .filter_map(|msg| match msg.clone().plain() {
+            Message::Chat { username, tag, content, channel, channel_id, .. } => {
+                let prefix = config.channel_prefix(channel, channel_id);
+                let name = format!("{}{}", prefix, config.author(channel, &username, tag.as_deref()));
+                Some(format!("*{}*: {}", escape(&name), escape(&content)))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// every character MarkdownV2 reserves has to be escaped, anywhere in the text.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\_*[]()~`>#+-=|{}.!".contains(c) { escaped.push('\\'); }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub struct TelegramSink {
+    config:  watch::Receiver<Config>,
+    webhook: Mutex<Webhook>,
+}
+
+impl TelegramSink {
+    pub fn new(config: watch::Receiver<Config>) -> Self {
+        let webhook = {
+            let config = config.borrow();
+            Webhook::paced(SEND_INTERVAL).dry_run(config.dry_run()).timeouts(config.timeouts())
+        };
+        Self { config, webhook: Mutex::new(webhook) }
+    }
+}
+
+#[async_trait]
+impl Sink for TelegramSink {
+    fn name(&self) -> &str { "telegram" }
+
+    async fn send(&self, msg: &Message) -> Result<()> { self.send_batch(std::slice::from_ref(msg)).await }
+
+    async fn send_batch(&self, batch: &[Message]) -> Result<()> {
+        let (url, max_attempts, payload) = {
+            let config = self.config.borrow();
+            let text = render(batch, &config);
+            if text.is_empty() { return Ok(()) }
+
+            let url = format!("https://api.telegram.org/bot{}/sendMessage", config.telegram_bot_token().expose());
+            let payload = Payload { chat_id: config.telegram_chat_id(), text, parse_mode: "MarkdownV2" };
+            (url, config.max_attempts(), serde_json::to_string(&payload)?)
+        };
+
+        self.webhook.lock().await.post(&url, &payload, max_attempts).await
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, watch};
+use tokio_tungstenite::tungstenite::Message as Frame;
+use crate::glue::Config;
+use crate::message::Message;
+use super::Sink;
+
+const STREAM_CAPACITY: usize = 256;
+
+// broadcasts every message as a json text frame to whoever is connected.
+pub struct WebSocketSink {
+    live: broadcast::Sender<Arc<str>>,
+}
+
+impl WebSocketSink {
+    pub fn new(config: watch::Receiver<Config>) -> Self {
+        let (addr, max_clients) = {
+            let config = config.borrow();
+            (config.websocket_addr().to_string(), config.websocket_max_clients())
+        };
+        let live = broadcast::channel(STREAM_CAPACITY).0;
+
+        tokio::spawn(serve(addr, max_clients, live.clone()));
+        Self { live }
+    }
+}
+
+#[async_trait]
+impl Sink for WebSocketSink {
+    fn name(&self) -> &str { "websocket" }
+
+    async fn send(&self, msg: &Message) -> Result<()> {
+        let json: Arc<str> = serde_json::to_string(msg)?.into();
+        // fails only while no client is connected, and then there is nobody the frame was for.
+        let _ = self.live.send(json);
+        Ok(())
+    }
+}
+
+async fn serve(addr: String, max_clients: usize, live: broadcast::Sender<Arc<str>>) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => { tracing::error!(addr, error = %e, "failed to bind websocket server"); return }
+    };
+    tracing::info!(addr, "serving websocket");
+
+    let clients = Arc::new(AtomicUsize::new(0));
+    loop {
+        let Ok((stream, peer)) = listener.accept().await else { continue };
+        if clients.load(Ordering::Relaxed) >= max_clients {
+            tracing::warn!(%peer, max_clients, "too many websocket clients, refusing connection");
+            continue
+        }
+
+        clients.fetch_add(1, Ordering::Relaxed);
+        let clients = clients.clone();
+        let live = live.subscribe();
+        tokio::spawn(async move {
+            handle(stream, live).await;
+            clients.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+async fn handle(stream: TcpStream, mut live: broadcast::Receiver<Arc<str>>) {
+    let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else { return };
+
+    loop {
+        tokio::select! {
+            msg = live.recv() => match msg {
+                Ok(json) => if socket.send(Frame::text(json.to_string())).await.is_err() { return },
+                // frames this client fell behind on are skipped, it carries on from the oldest one still buffered.
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            // clients only ever send pings and the close handshake, which tungstenite answers itself.
+            frame = socket.next() => match frame {
+                Some(Ok(Frame::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+        }
+    }
+}
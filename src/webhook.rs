@@ -0,0 +1,161 @@
+use anyhow::{bail, Result};
+use std::time::Duration;
+use reqwest::{Method, Response, StatusCode};
+use tokio::time::Instant;
+use crate::metrics;
+
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+const RATE_LIMIT_FALLBACK: Duration = Duration::from_secs(1);
+const MAX_RATE_LIMITED: u32 = 10;
+
+// the endpoint refused the message itself, sending it again will not help.
+#[derive(Debug)]
+pub struct Rejected(StatusCode);
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "webhook rejected message with {}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+pub struct Webhook {
+    client: reqwest::Client,
+    // shared by all webhook urls, so a rate limit on one paces every following send.
+    not_before: Instant,
+    interval:   Duration,
+    dry_run:    bool,
+}
+
+impl Default for Webhook {
+    fn default() -> Self { Self::new() }
+}
+
+impl Webhook {
+    pub fn new() -> Self { Self::paced(Duration::ZERO) }
+
+    // spaces consecutive sends at least `interval` apart, for endpoints that publish a limit but no headers.
+    pub fn paced(interval: Duration) -> Self {
+        Self { client: reqwest::Client::new(), not_before: Instant::now(), interval, dry_run: false }
+    }
+
+    // prints every payload instead of posting it, everything up to the request still runs.
+    pub fn dry_run(self, dry_run: bool) -> Self { Self { dry_run, ..self } }
+
+    // a hung request would stall its sink for good, so every request gives up eventually and counts as a failed attempt.
+    pub fn timeouts(self, (connect, request): (Duration, Duration)) -> Self {
+        let mut client = reqwest::Client::builder();
+        if !connect.is_zero() { client = client.connect_timeout(connect); }
+        if !request.is_zero() { client = client.timeout(request); }
+        match client.build() {
+            Ok(client) => Self { client, ..self },
+            Err(e) => { tracing::error!(error = %e, "failed to build http client, continuing without timeouts"); self }
+        }
+    }
+
+    pub async fn post(&mut self, url: &str, payload: &str, max_attempts: u32) -> Result<()> {
+        let headers = [("Content-Type".to_string(), "application/json".to_string())];
+        self.request(Method::POST, url, &headers, payload, max_attempts).await
+    }
+
+    // like `post`, for endpoints that want another method or headers.
+    pub async fn request(
+        &mut self,
+        method: Method,
+        url: &str,
+        headers: &[(String, String)],
+        payload: &str,
+        max_attempts: u32,
+    ) -> Result<()> {
+        if self.dry_run {
+            // the url itself carries the webhook secret, only where it points is shown.
+            let host = reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string));
+            println!("[dry run] {} {}: {}", method, host.as_deref().unwrap_or("?"), payload);
+            return Ok(())
+        }
+
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 1;
+        let mut rate_limited = 0;
+
+        loop {
+            tokio::time::sleep_until(self.not_before).await;
+            self.defer(self.interval);
+
+            let mut request = self.client.request(method.clone(), url);
+            for (name, value) in headers { request = request.header(name, value); }
+            let response = request.body(payload.to_string()).send().await;
+
+            match response {
+                Ok(r) if r.status().is_success() => {
+                    self.pace(&r);
+                    return Ok(())
+                }
+                Ok(r) if r.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    rate_limited += 1;
+                    if rate_limited > MAX_RATE_LIMITED {
+                        bail!("still rate limited after {} retries", MAX_RATE_LIMITED);
+                    }
+                    let retry_after = retry_after(r).await;
+                    metrics::retried();
+                    self.defer(retry_after);
+                    continue
+                }
+                Ok(r) if r.status().is_client_error() =>
+                    return Err(Rejected(r.status()).into()),
+                Err(e) if e.is_timeout() => tracing::warn!(attempt, "webhook request timed out"),
+                _ => {}
+            }
+
+            if attempt >= max_attempts {
+                bail!("giving up after {} attempts", max_attempts);
+            }
+            attempt += 1;
+            metrics::retried();
+
+            let jitter = rand::random_range(0..=delay.as_millis() as u64 / 2);
+            tokio::time::sleep(delay + Duration::from_millis(jitter)).await;
+            delay *= 2;
+        }
+    }
+
+    fn defer(&mut self, duration: Duration) {
+        self.not_before = self.not_before.max(Instant::now() + duration);
+    }
+
+    // discord announces an exhausted bucket ahead of time, wait it out instead of running into a 429.
+    fn pace(&mut self, response: &Response) {
+        let remaining = header(response, "x-ratelimit-remaining");
+        let reset_after = header(response, "x-ratelimit-reset-after").and_then(seconds);
+
+        if let (Some("0"), Some(reset_after)) = (remaining, reset_after) {
+            self.defer(reset_after);
+        }
+    }
+}
+
+async fn retry_after(response: Response) -> Duration {
+    if let Some(retry_after) = header(&response, "retry-after").and_then(seconds) {
+        return retry_after;
+    }
+
+    response.text().await.ok()
+        .and_then(|body| serde_json::from_str::<serde_json::Value>(&body).ok())
+        .and_then(|body| {
+            // discord reports it at the top level, telegram nests it under `parameters`.
+            body.get("retry_after")
+                .or_else(|| body.pointer("/parameters/retry_after"))
+                .and_then(serde_json::Value::as_f64)
+        })
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .unwrap_or(RATE_LIMIT_FALLBACK)
+}
+
+fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+    response.headers().get(name).and_then(|value| value.to_str().ok())
+}
+
+fn seconds(value: &str) -> Option<Duration> {
+    value.trim().parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
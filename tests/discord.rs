@@ -0,0 +1,141 @@
+use std::time::Duration;
+use chatbridge::bridge::consume;
+use chatbridge::glue::{Config, Overflow};
+use chatbridge::message::{Message, Sequence};
+use chatbridge::outbox;
+use chatbridge::sink::{DiscordWebhookSink, Sink};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+
+struct Request {
+    headers: Vec<(String, String)>,
+    body:    serde_json::Value,
+    at:      Instant,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(other, _)| other.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+// answers with the scripted responses in order, then with 204, and hands every request it read to the test.
+async fn mock(responses: Vec<&'static str>) -> (String, mpsc::UnboundedReceiver<Request>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut responses = responses.into_iter();
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else { return };
+            // reqwest keeps the connection alive, so one connection can carry every request.
+            while let Some(request) = read(&mut stream).await {
+                let _ = tx.send(request);
+                let response = responses.next().unwrap_or("HTTP/1.1 204 No Content\r\ncontent-length: 0\r\n\r\n");
+                if stream.write_all(response.as_bytes()).await.is_err() { break }
+            }
+        }
+    });
+
+    // routes are taken as configured, which points claim chat of "Test" at the mock instead of discord.
+    let route = format!("[[routes]]\nname = \"Test\"\nwebhook_url = \"http://{}/api/webhooks/1/token\"\n", addr);
+    (route, rx)
+}
+
+async fn read(stream: &mut TcpStream) -> Option<Request> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let end = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") { break end + 4 }
+        let read = stream.read(&mut chunk).await.ok().filter(|read| *read > 0)?;
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..end]).to_string();
+    let headers = head
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect::<Vec<_>>();
+    let length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    while buffer.len() < end + length {
+        let read = stream.read(&mut chunk).await.ok().filter(|read| *read > 0)?;
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = serde_json::from_slice(&buffer[end..end + length]).unwrap_or_default();
+    Some(Request { headers, body, at: Instant::now() })
+}
+
+fn config(toml: &str) -> watch::Receiver<Config> {
+    watch::channel(toml::from_str::<Config>(toml).unwrap()).1
+}
+
+fn chat() -> Message { Message::claim("Alice".to_string(), "Test", "hello".to_string()) }
+
+#[tokio::test]
+async fn posts_json_payload() {
+    let (route, mut requests) = mock(Vec::new()).await;
+    let sink = DiscordWebhookSink::new(config(&route));
+
+    sink.send_batch(&[chat()]).await.unwrap();
+
+    let request = requests.recv().await.unwrap();
+    assert_eq!(request.header("content-type"), Some("application/json"));
+    assert_eq!(request.body["content"], "hello");
+    assert!(request.body["username"].as_str().unwrap().contains("Alice"));
+    assert_eq!(request.body["allowed_mentions"]["parse"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn retries_server_errors() {
+    let (route, mut requests) = mock(vec!["HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\n\r\n"]).await;
+    let sink = DiscordWebhookSink::new(config(&route));
+
+    sink.send_batch(&[chat()]).await.unwrap();
+
+    let first = requests.recv().await.unwrap();
+    let second = requests.recv().await.unwrap();
+    assert_eq!(first.body, second.body);
+    assert!(requests.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn waits_out_retry_after() {
+    let limited = concat!(
+        "HTTP/1.1 429 Too Many Requests\r\ncontent-type: application/json\r\ncontent-length: 20\r\n\r\n",
+        r#"{"retry_after": 0.5}"#,
+    );
+    let (route, mut requests) = mock(vec![limited]).await;
+    let sink = DiscordWebhookSink::new(config(&route));
+
+    sink.send_batch(&[chat()]).await.unwrap();
+
+    let first = requests.recv().await.unwrap();
+    let second = requests.recv().await.unwrap();
+    assert!(second.at - first.at >= Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn disconnect_stops_consume() {
+    let (route, mut requests) = mock(Vec::new()).await;
+    let config = config(&format!("echo = false\n{}", route));
+    let sinks: Vec<Box<dyn Sink>> = vec![Box::new(DiscordWebhookSink::new(config.clone()))];
+    let (tx, rx) = outbox::bounded(16, Overflow::Block);
+
+    let consumer = tokio::spawn(consume(rx, config, sinks, Sequence::default()));
+    tx.send(chat()).await.unwrap();
+    tx.send(Message::Disconnect).await.unwrap();
+
+    // the outbox stays open, only the disconnect can end consume, and the message before it still goes out.
+    tokio::time::timeout(Duration::from_secs(5), consumer).await.expect("consume kept running").unwrap();
+    assert_eq!(requests.recv().await.unwrap().body["content"], "hello");
+}